@@ -0,0 +1,3 @@
+-- Add migration script here
+-- add_status_to_subscriptions
+ALTER TABLE subscriptions ADD COLUMN status TEXT NULL;
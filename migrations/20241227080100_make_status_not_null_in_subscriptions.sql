@@ -0,0 +1,8 @@
+-- Add migration script here
+-- make_status_not_null_in_subscriptions
+BEGIN;
+	UPDATE subscriptions
+		SET status = 'confirmed'
+		WHERE status IS NULL;
+	ALTER TABLE subscriptions ALTER COLUMN status SET NOT NULL;
+COMMIT;
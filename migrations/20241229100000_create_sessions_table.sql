@@ -0,0 +1,8 @@
+-- Add migration script here
+-- create_sessions_table
+CREATE TABLE sessions(
+	session_key TEXT PRIMARY KEY,
+	state JSONB NOT NULL,
+	expires_at timestamptz NOT NULL
+);
+CREATE INDEX sessions_expires_at_idx ON sessions (expires_at);
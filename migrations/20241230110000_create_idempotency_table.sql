@@ -0,0 +1,15 @@
+-- Add migration script here
+-- create_idempotency_table
+CREATE TYPE header_pair AS (
+	name TEXT,
+	value BYTEA
+);
+CREATE TABLE idempotency(
+	scope TEXT NOT NULL,
+	idempotency_key TEXT NOT NULL,
+	response_status_code SMALLINT NULL,
+	response_headers header_pair[] NULL,
+	response_body BYTEA NULL,
+	created_at timestamptz NOT NULL,
+	PRIMARY KEY (scope, idempotency_key)
+);
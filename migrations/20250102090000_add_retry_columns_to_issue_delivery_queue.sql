@@ -0,0 +1,6 @@
+-- Add migration script here
+-- add_retry_columns_to_issue_delivery_queue
+ALTER TABLE issue_delivery_queue
+	ADD COLUMN n_retries SMALLINT NOT NULL DEFAULT 0,
+	ADD COLUMN execute_after timestamptz NOT NULL DEFAULT now(),
+	ADD COLUMN last_error TEXT NULL;
@@ -0,0 +1,3 @@
+-- Add migration script here
+-- add_unsubscribed_at_to_subscriptions
+ALTER TABLE subscriptions ADD COLUMN unsubscribed_at timestamptz NULL;
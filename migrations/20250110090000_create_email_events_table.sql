@@ -0,0 +1,16 @@
+-- Add migration script here
+-- create_email_events_table
+CREATE TABLE email_events(
+	id uuid NOT NULL,
+	PRIMARY KEY (id),
+	subscriber_id uuid NULL
+		REFERENCES subscriptions (id),
+	email TEXT NOT NULL,
+	event_type TEXT NOT NULL,
+	detail TEXT NULL,
+	message_id TEXT NOT NULL,
+	occurred_at timestamptz NOT NULL,
+	received_at timestamptz NOT NULL,
+	UNIQUE (message_id, event_type, email)
+);
+CREATE INDEX email_events_subscriber_id_idx ON email_events (subscriber_id);
@@ -0,0 +1,3 @@
+-- Add migration script here
+-- add_suppressed_at_to_subscriptions
+ALTER TABLE subscriptions ADD COLUMN suppressed_at timestamptz NULL;
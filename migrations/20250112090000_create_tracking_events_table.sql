@@ -0,0 +1,14 @@
+-- Add migration script here
+-- create_tracking_events_table
+CREATE TABLE tracking_events(
+	id uuid NOT NULL,
+	PRIMARY KEY (id),
+	newsletter_issue_id uuid NOT NULL
+		REFERENCES newsletter_issues (newsletter_issue_id),
+	subscriber_id uuid NOT NULL
+		REFERENCES subscriptions (id),
+	event_type TEXT NOT NULL,
+	url TEXT NULL,
+	occurred_at timestamptz NOT NULL
+);
+CREATE INDEX tracking_events_newsletter_issue_id_idx ON tracking_events (newsletter_issue_id);
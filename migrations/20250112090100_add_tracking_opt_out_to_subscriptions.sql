@@ -0,0 +1,3 @@
+-- Add migration script here
+-- add_tracking_opt_out_to_subscriptions
+ALTER TABLE subscriptions ADD COLUMN tracking_opt_out BOOLEAN NOT NULL DEFAULT false;
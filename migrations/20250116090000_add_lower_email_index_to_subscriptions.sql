@@ -0,0 +1,4 @@
+-- Add migration script here
+-- add_lower_email_index_to_subscriptions
+-- 邮件服务商回调中的地址大小写可能与订阅时不同，按 lower(email) 匹配订阅者
+CREATE INDEX subscriptions_lower_email_idx ON subscriptions (lower(email));
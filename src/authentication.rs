@@ -0,0 +1,243 @@
+pub mod middleware;
+
+use actix_web::http::header::HeaderMap;
+use anyhow::Context;
+use argon2::password_hash::SaltString;
+use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
+use base64::Engine;
+use secrecy::{ExposeSecret, SecretBox};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::configuration::AdminSettings;
+use crate::telemetry::spawn_blocking_with_tracing;
+
+#[derive(Debug)]
+pub struct Credentials {
+    pub username: String,
+    pub password: SecretBox<String>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum AuthError {
+    #[error("Invalid credentials.")]
+    InvalidCredentials(#[source] anyhow::Error),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+/// 从 `Authorization: Basic ...` 请求头中解析用户凭证
+pub fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
+    let header_value = headers
+        .get("Authorization")
+        .context("The 'Authorization' header was missing")?
+        .to_str()
+        .context("The 'Authorization' header was not a valid UTF8 string.")?;
+    let base64encoded_segment = header_value
+        .strip_prefix("Basic ")
+        .context("The authorization scheme was not 'Basic'.")?;
+    let decoded_bytes = base64::engine::general_purpose::STANDARD
+        .decode(base64encoded_segment)
+        .context("Failed to base64-decode 'Basic' credentials.")?;
+    let decoded_credentials = String::from_utf8(decoded_bytes)
+        .context("The decoded credential string is not valid UTF8.")?;
+
+    let mut credentials = decoded_credentials.splitn(2, ':');
+    let username = credentials
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("A username must be provided in 'Basic' auth."))?
+        .to_string();
+    let password = credentials
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("A password must be provided in 'Basic' auth."))?
+        .to_string();
+
+    Ok(Credentials {
+        username,
+        password: SecretBox::new(Box::new(password)),
+    })
+}
+
+#[tracing::instrument(name = "Validate credentials", skip(credentials, pool))]
+pub async fn validate_credentials(
+    credentials: Credentials,
+    pool: &PgPool,
+) -> Result<Uuid, AuthError> {
+    let mut user_id = None;
+    // 用户不存在时仍然执行一次哈希校验，避免通过响应耗时枚举用户名
+    let mut expected_password_hash = SecretBox::new(Box::new(
+        "$argon2id$v=19$m=15000,t=2,p=1$\
+        gZiV/M1gPc22ElAH/Jh1Hw$\
+        CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno"
+            .to_string(),
+    ));
+
+    if let Some((stored_user_id, stored_password_hash)) =
+        get_stored_credentials(&credentials.username, pool).await?
+    {
+        user_id = Some(stored_user_id);
+        expected_password_hash = stored_password_hash;
+    }
+
+    spawn_blocking_with_tracing(move || {
+        verify_password_hash(expected_password_hash, credentials.password)
+    })
+    .await
+    .context("Failed to spawn blocking task.")??;
+
+    user_id
+        .ok_or_else(|| anyhow::anyhow!("Unknown username."))
+        .map_err(AuthError::InvalidCredentials)
+}
+
+#[tracing::instrument(
+    name = "Verify password hash",
+    skip(expected_password_hash, password_candidate)
+)]
+fn verify_password_hash(
+    expected_password_hash: SecretBox<String>,
+    password_candidate: SecretBox<String>,
+) -> Result<(), AuthError> {
+    let expected_password_hash = PasswordHash::new(expected_password_hash.expose_secret())
+        .context("Failed to parse hash in PHC string format.")?;
+
+    Argon2::default()
+        .verify_password(
+            password_candidate.expose_secret().as_bytes(),
+            &expected_password_hash,
+        )
+        .context("Invalid password.")
+        .map_err(AuthError::InvalidCredentials)
+}
+
+#[tracing::instrument(name = "Change password", skip(password, pool))]
+pub async fn change_password(
+    user_id: Uuid,
+    password: SecretBox<String>,
+    pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    let password_hash = spawn_blocking_with_tracing(move || compute_password_hash(password))
+        .await?
+        .context("Failed to hash password")?;
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET password_hash = $1
+        WHERE user_id = $2
+        "#,
+        password_hash.expose_secret(),
+        user_id,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to change user's password in the database.")?;
+    Ok(())
+}
+
+/// 按配置创建管理员账号，同名账号已存在时保持不变；返回是否新建了账号
+#[tracing::instrument(name = "Provision admin", skip_all)]
+pub async fn provision_admin(admin: &AdminSettings, pool: &PgPool) -> Result<bool, anyhow::Error> {
+    let password = SecretBox::new(Box::new(admin.password.expose_secret().clone()));
+    let password_hash = spawn_blocking_with_tracing(move || compute_password_hash(password))
+        .await?
+        .context("Failed to hash password")?;
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO users (user_id, username, password_hash)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (username) DO NOTHING
+        "#,
+        Uuid::new_v4(),
+        admin.username,
+        password_hash.expose_secret(),
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store the admin account in the database.")?;
+    let created = result.rows_affected() > 0;
+    if created {
+        tracing::info!("Created the configured admin account.");
+    }
+    Ok(created)
+}
+
+fn compute_password_hash(password: SecretBox<String>) -> Result<SecretBox<String>, anyhow::Error> {
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    let password_hash = Argon2::new(
+        Algorithm::Argon2id,
+        Version::V0x13,
+        Params::new(15000, 2, 1, None).unwrap(),
+    )
+    .hash_password(password.expose_secret().as_bytes(), &salt)?
+    .to_string();
+    Ok(SecretBox::new(Box::new(password_hash)))
+}
+
+#[tracing::instrument(name = "Get stored credentials", skip(username, pool))]
+async fn get_stored_credentials(
+    username: &str,
+    pool: &PgPool,
+) -> Result<Option<(Uuid, SecretBox<String>)>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT user_id, password_hash
+        FROM users
+        WHERE username = $1
+        "#,
+        username,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to retrieve stored credentials.")?
+    .map(|row| (row.user_id, SecretBox::new(Box::new(row.password_hash))));
+    Ok(row)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::authentication::basic_authentication;
+    use actix_web::http::header::{HeaderMap, HeaderValue};
+    use base64::Engine;
+    use claim::{assert_err, assert_ok};
+    use secrecy::ExposeSecret;
+
+    fn headers_with_authorization(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            actix_web::http::header::AUTHORIZATION,
+            HeaderValue::from_str(value).unwrap(),
+        );
+        headers
+    }
+
+    #[test]
+    fn valid_basic_credentials_are_parsed() {
+        let encoded = base64::engine::general_purpose::STANDARD.encode("admin:p@ss:word");
+        let headers = headers_with_authorization(&format!("Basic {}", encoded));
+
+        let credentials = basic_authentication(&headers);
+
+        assert_ok!(&credentials);
+        let credentials = credentials.unwrap();
+        assert_eq!(credentials.username, "admin");
+        assert_eq!(credentials.password.expose_secret(), "p@ss:word");
+    }
+
+    #[test]
+    fn missing_authorization_header_is_rejected() {
+        assert_err!(basic_authentication(&HeaderMap::new()));
+    }
+
+    #[test]
+    fn non_basic_scheme_is_rejected() {
+        let headers = headers_with_authorization("Bearer abcdef");
+        assert_err!(basic_authentication(&headers));
+    }
+
+    #[test]
+    fn credentials_without_a_password_are_rejected() {
+        let encoded = base64::engine::general_purpose::STANDARD.encode("admin");
+        let headers = headers_with_authorization(&format!("Basic {}", encoded));
+        assert_err!(basic_authentication(&headers));
+    }
+}
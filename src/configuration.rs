@@ -1,21 +1,51 @@
+mod validation;
+
+pub use validation::{InvalidSettings, SettingError};
+
 use std::time::Duration;
 
+use anyhow::Context;
+use log::LevelFilter;
 use secrecy::{ExposeSecret, SecretBox};
+use serde::Deserialize;
+use serde_aux::field_attributes::{deserialize_bool_from_anything, deserialize_number_from_string};
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions, PgSslMode};
+use sqlx::ConnectOptions;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
 
-use crate::domain::subscriber_email::SubscriberEmail;
+use crate::domain::subscriber_email::{SubscriberEmail, SubscriberEmailError};
+use crate::domain::tracking_token::TrackingLinks;
+use crate::domain::unsubscribe_token::UnsubscribeLinks;
+use crate::email_client::{
+    EmailBackend, EmailClient, EmailTemplates, FileTransport, LogTransport, PostmarkTransport,
+    SmtpTls, SmtpTransport,
+};
+use crate::issue_delivery_worker::RetryPolicy;
+use crate::routes::WebhookCredentials;
 
-#[derive(Debug)]
-pub enum Environment {
-    Local,
-    Production,
-}
+/// 不能作为环境名的保留文件名
+const RESERVED_ENVIRONMENT_NAMES: [&str; 2] = ["base", "override"];
+
+/// 部署环境，例如 `local`、`staging`、`ci`，对应 `configuration/<name>.yaml`
+#[derive(serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub struct Environment(String);
 
 impl Environment {
-    pub fn as_str(&self) -> &'static str {
-        match self {
-            Environment::Local => "local",
-            Environment::Production => "production",
-        }
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// 本地开发环境，可以使用仓库中提交的开发用密钥
+    pub fn is_local(&self) -> bool {
+        self.0 == "local"
+    }
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Self("local".into())
     }
 }
 
@@ -23,93 +53,641 @@ impl TryFrom<String> for Environment {
     type Error = String;
 
     fn try_from(s: String) -> Result<Self, Self::Error> {
-        match s.to_lowercase().as_str() {
-            "local" => Ok(Environment::Local),
-            "production" => Ok(Environment::Production),
-            _ => Err(format!("{} is not a supported environment", s)),
+        let name = s.trim().to_lowercase();
+        // 环境名会拼进文件路径，只允许安全的字符
+        let is_valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !is_valid {
+            Err(format!(
+                "{} is not a valid environment name. Use letters, digits, '-' and '_' only.",
+                s
+            ))
+        } else if RESERVED_ENVIRONMENT_NAMES.contains(&name.as_str()) {
+            Err(format!(
+                "{} is a reserved name and cannot be an environment.",
+                s
+            ))
+        } else {
+            Ok(Environment(name))
         }
     }
 }
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, Debug)]
 pub struct Settings {
+    /// 由 `APP_ENVIRONMENT` 决定，不需要写在配置文件中
+    #[serde(default)]
+    pub environment: Environment,
     pub application: ApplicationSettings,
     pub database: DatabaseSettings,
     pub email_client: EmailClientSettings,
+    pub delivery_worker: DeliveryWorkerSettings,
+    pub health: HealthSettings,
+    pub webhooks: WebhookSettings,
+    pub tracking: TrackingSettings,
+    pub metrics: MetricsSettings,
+    pub telemetry: TelemetrySettings,
+    pub log: LogSettings,
+    /// 配置后，启动时若不存在该用户名则创建管理员账号
+    pub admin: Option<AdminSettings>,
+    /// 配置的来源，按优先级从低到高排列；环境变量只记录名称
+    #[serde(skip)]
+    pub sources: Vec<String>,
 }
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, Debug)]
 pub struct ApplicationSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
     pub port: u16,
     pub host: String,
+    pub base_url: String,
+    pub hmac_secret: SecretBox<String>,
+    pub session_store: SessionStoreKind,
+}
+
+impl ApplicationSettings {
+    pub fn unsubscribe_links(&self) -> UnsubscribeLinks {
+        UnsubscribeLinks::new(
+            self.base_url.clone(),
+            SecretBox::new(Box::new(self.hmac_secret.expose_secret().clone())),
+        )
+    }
+
+    pub fn tracking_links(&self) -> TrackingLinks {
+        TrackingLinks::new(
+            self.base_url.clone(),
+            SecretBox::new(Box::new(self.hmac_secret.expose_secret().clone())),
+        )
+    }
+}
+
+/// session 数据的存储后端
+#[derive(serde::Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionStoreKind {
+    Memory,
+    Postgres,
 }
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, Debug)]
 pub struct EmailClientSettings {
+    pub transport: EmailTransportKind,
     pub base_url: String,
     pub sender_email: String,
     pub authorization_token: SecretBox<String>,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
     pub timeout_milliseconds: u64,
+    pub smtp: SmtpSettings,
+    /// `file` 通道写入 `.eml` 文件的目录
+    pub output_directory: String,
+    /// 邮件模板所在的目录
+    pub templates_directory: String,
+}
+
+/// 邮件的发送通道
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailTransportKind {
+    Postmark,
+    Smtp,
+    File,
+    Log,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct SmtpSettings {
+    pub host: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub port: u16,
+    pub tls: SmtpTls,
+    pub username: Option<String>,
+    pub password: Option<SecretBox<String>>,
 }
 
 impl EmailClientSettings {
-    pub fn sender(&self) -> Result<SubscriberEmail, String> {
+    pub fn sender(&self) -> Result<SubscriberEmail, SubscriberEmailError> {
         SubscriberEmail::parse(self.sender_email.clone())
     }
 
     pub fn timeout(&self) -> Duration {
         Duration::from_millis(self.timeout_milliseconds)
     }
+
+    pub fn transport(&self) -> Result<EmailBackend, anyhow::Error> {
+        let transport = match self.transport {
+            EmailTransportKind::Postmark => EmailBackend::Postmark(PostmarkTransport::new(
+                self.base_url.clone(),
+                SecretBox::new(Box::new(self.authorization_token.expose_secret().clone())),
+                self.timeout(),
+            )?),
+            EmailTransportKind::Smtp => {
+                let credentials = match (&self.smtp.username, &self.smtp.password) {
+                    (Some(username), Some(password)) => Some((username.clone(), password)),
+                    (None, None) => None,
+                    _ => anyhow::bail!("SMTP username and password must be set together."),
+                };
+                EmailBackend::Smtp(SmtpTransport::new(
+                    &self.smtp.host,
+                    self.smtp.port,
+                    self.smtp.tls,
+                    credentials,
+                    self.timeout(),
+                )?)
+            }
+            EmailTransportKind::File => {
+                EmailBackend::File(FileTransport::new(&self.output_directory))
+            }
+            EmailTransportKind::Log => EmailBackend::Log(LogTransport),
+        };
+        Ok(transport)
+    }
+
+    pub fn templates(&self) -> Result<EmailTemplates, anyhow::Error> {
+        EmailTemplates::load(&self.templates_directory)
+    }
+
+    pub fn client(&self) -> Result<EmailClient, anyhow::Error> {
+        let sender_email = self.sender().context("Invalid sender email address.")?;
+        let transport = self
+            .transport()
+            .context("Invalid email transport settings.")?;
+        let templates = self.templates().context("Invalid email templates.")?;
+        Ok(EmailClient::new(sender_email, transport, templates))
+    }
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct DeliveryWorkerSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_retries: u16,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub base_backoff_milliseconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_backoff_milliseconds: u64,
+}
+
+impl DeliveryWorkerSettings {
+    pub fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_retries: self.max_retries,
+            base_backoff: Duration::from_millis(self.base_backoff_milliseconds),
+            max_backoff: Duration::from_millis(self.max_backoff_milliseconds),
+        }
+    }
+}
+
+/// 就绪探针的配置
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct HealthSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub timeout_milliseconds: u64,
+    #[serde(deserialize_with = "deserialize_bool_from_anything")]
+    pub check_email_provider: bool,
+}
+
+impl HealthSettings {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_milliseconds)
+    }
+}
+
+/// 简报的打开与点击追踪
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct TrackingSettings {
+    /// 开启后，发给未选择关闭追踪的订阅者的简报会包含追踪像素，链接会改写为追踪链接
+    #[serde(deserialize_with = "deserialize_bool_from_anything")]
+    pub enabled: bool,
+}
+
+/// Prometheus 指标接口，监听在与应用不同的端口上，不应对公网开放
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct MetricsSettings {
+    pub host: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub port: u16,
+}
+
+/// trace 的导出配置
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct TelemetrySettings {
+    /// OTLP/HTTP 的 trace 接收地址，例如 `http://127.0.0.1:4318/v1/traces`；不配置时不导出 trace
+    pub otlp_endpoint: Option<String>,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub otlp_timeout_milliseconds: u64,
+}
+
+impl TelemetrySettings {
+    pub fn otlp_timeout(&self) -> Duration {
+        Duration::from_millis(self.otlp_timeout_milliseconds)
+    }
+}
+
+/// 日志的格式与输出位置
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct LogSettings {
+    /// `EnvFilter` 语法的过滤规则，例如 `info,sqlx=debug`；设置了 `RUST_LOG` 时以环境变量为准
+    pub filter: String,
+    pub format: LogFormat,
+    /// 配置后写入按时间滚动的日志文件，否则输出到标准输出
+    pub file: Option<LogFileSettings>,
+    pub redaction: LogRedaction,
+}
+
+impl LogSettings {
+    pub fn writer(&self) -> Result<BoxMakeWriter, anyhow::Error> {
+        let writer = match &self.file {
+            Some(file) => BoxMakeWriter::new(file.appender()?),
+            None => BoxMakeWriter::new(std::io::stdout),
+        };
+        Ok(writer)
+    }
+}
+
+/// 日志格式：`bunyan` 为 JSON，便于日志平台采集；`compact` 与 `pretty` 便于本地开发时阅读
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Bunyan,
+    Compact,
+    Pretty,
+}
+
+/// 邮箱、姓名、令牌等个人信息写入日志的方式：`hash` 输出以 `application.hmac_secret` 为密钥的 HMAC，
+/// 仍可以关联同一个人的多条日志；`mask` 完全隐藏；`off` 输出原值，只应在本地开发时使用
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRedaction {
+    Hash,
+    Mask,
+    Off,
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct LogFileSettings {
+    pub directory: String,
+    /// 日志文件名的前缀，滚动后的文件名为 `<prefix>.<日期>`
+    pub prefix: String,
+    pub rotation: LogRotation,
+    /// 最多保留的日志文件数，更早的文件会被删除
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_files: usize,
+}
+
+/// 日志文件的滚动周期
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    Minutely,
+    Hourly,
+    Daily,
+    Never,
+}
+
+impl LogFileSettings {
+    pub fn appender(&self) -> Result<RollingFileAppender, anyhow::Error> {
+        let rotation = match self.rotation {
+            LogRotation::Minutely => Rotation::MINUTELY,
+            LogRotation::Hourly => Rotation::HOURLY,
+            LogRotation::Daily => Rotation::DAILY,
+            LogRotation::Never => Rotation::NEVER,
+        };
+        let appender = RollingFileAppender::builder()
+            .rotation(rotation)
+            .filename_prefix(&self.prefix)
+            .max_log_files(self.max_files)
+            .build(&self.directory)?;
+        Ok(appender)
+    }
+}
+
+/// 启动时创建的管理员账号；同名账号已存在时不做任何修改，之后请在后台修改密码
+#[derive(serde::Deserialize, Debug)]
+pub struct AdminSettings {
+    pub username: String,
+    pub password: SecretBox<String>,
+}
+
+/// 邮件服务商回调接口的 Basic 认证凭证
+#[derive(serde::Deserialize, Debug)]
+pub struct WebhookSettings {
+    pub username: String,
+    pub password: SecretBox<String>,
+}
+
+impl WebhookSettings {
+    pub fn credentials(&self) -> WebhookCredentials {
+        WebhookCredentials {
+            username: self.username.clone(),
+            password: SecretBox::new(Box::new(self.password.expose_secret().clone())),
+        }
+    }
 }
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, Debug)]
 pub struct DatabaseSettings {
     pub username: String,
     pub password: SecretBox<String>,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
     pub port: u16,
     pub host: String,
     pub database_name: String,
+    #[serde(deserialize_with = "deserialize_bool_from_anything")]
+    pub require_ssl: bool,
+    /// 用于校验服务端证书的 CA 证书路径，配置后会同时校验证书链与主机名
+    pub ssl_root_cert: Option<String>,
+    #[serde(deserialize_with = "deserialize_level_filter")]
+    pub statement_log_level: LevelFilter,
+    pub application_name: Option<String>,
+    pub pool: PoolSettings,
 }
 
 impl DatabaseSettings {
-    pub fn connection_string(&self) -> SecretBox<String> {
-        SecretBox::new(Box::new(format!(
-            "postgres://{}:{}@{}:{}/{}",
-            self.username,
-            self.password.expose_secret(),
-            self.host,
-            self.port,
-            self.database_name
-        )))
+    /// 不指定数据库的连接参数，用于创建数据库等管理操作
+    pub fn without_db(&self) -> PgConnectOptions {
+        let ssl_mode = match (self.require_ssl, &self.ssl_root_cert) {
+            (true, Some(_)) => PgSslMode::VerifyFull,
+            (true, None) => PgSslMode::Require,
+            (false, _) => PgSslMode::Prefer,
+        };
+        let mut options = PgConnectOptions::new()
+            .host(&self.host)
+            .username(&self.username)
+            .password(self.password.expose_secret())
+            .port(self.port)
+            .ssl_mode(ssl_mode);
+        if let Some(ssl_root_cert) = &self.ssl_root_cert {
+            options = options.ssl_root_cert(ssl_root_cert);
+        }
+        if let Some(application_name) = &self.application_name {
+            options = options.application_name(application_name);
+        }
+        options
     }
 
-    pub fn connection_string_without_db(&self) -> SecretBox<String> {
-        SecretBox::new(Box::new(format!(
-            "postgres://{}:{}@{}:{}",
-            self.username,
-            self.password.expose_secret(),
-            self.host,
-            self.port,
-        )))
+    pub fn with_db(&self) -> PgConnectOptions {
+        self.without_db()
+            .database(&self.database_name)
+            .log_statements(self.statement_log_level)
     }
 }
 
+/// 按名称解析日志级别，大小写不敏感，例如 `debug`、`OFF`
+fn deserialize_level_filter<'de, D>(deserializer: D) -> Result<LevelFilter, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let level = String::deserialize(deserializer)?;
+    level.parse().map_err(serde::de::Error::custom)
+}
+
+/// 数据库连接池的配置
+#[derive(serde::Deserialize, Debug)]
+pub struct PoolSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub min_connections: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_connections: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub acquire_timeout_milliseconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub idle_timeout_seconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_lifetime_seconds: u64,
+}
+
+impl PoolSettings {
+    pub fn options(&self) -> PgPoolOptions {
+        PgPoolOptions::new()
+            .min_connections(self.min_connections)
+            .max_connections(self.max_connections)
+            .acquire_timeout(Duration::from_millis(self.acquire_timeout_milliseconds))
+            .idle_timeout(Duration::from_secs(self.idle_timeout_seconds))
+            .max_lifetime(Duration::from_secs(self.max_lifetime_seconds))
+    }
+}
+
+/// 依次合并 `base.yaml`、`<environment>.yaml`、可选的 `override.yaml`（不纳入版本库，用于本机调试）
+/// 与 `APP_` 前缀的环境变量，例如 `APP_DATABASE__PASSWORD` 会覆盖 `database.password`。
+/// `APP_DATABASE__PASSWORD_FILE=/run/secrets/db_password` 形式的变量会读取文件内容作为对应配置项的值，
+/// 便于在容器中挂载密钥文件。
 pub fn get_configuration() -> Result<Settings, config::ConfigError> {
-    let base_path = std::env::current_dir().expect("Failed to determine the current directory");
+    let base_path = std::env::current_dir().map_err(|e| {
+        config::ConfigError::Message(format!("Failed to determine the current directory: {}", e))
+    })?;
     let configuration_directory = base_path.join("configuration");
 
     let environment: Environment = std::env::var("APP_ENVIRONMENT")
         .unwrap_or_else(|_| "local".into())
         .try_into()
-        .expect("Failed to parse APP_ENVIRONMENT.");
-    let environment_filename = format!("{}.yaml", environment.as_str());
-    let settings = config::Config::builder()
-        .add_source(config::File::from(
-            configuration_directory.join("base.yaml"),
-        ))
-        .add_source(config::File::from(
-            configuration_directory.join(&environment_filename),
-        ))
-        .build()?;
-    settings.try_deserialize::<Settings>()
+        .map_err(config::ConfigError::Message)?;
+    let base_file = configuration_directory.join("base.yaml");
+    let environment_file = configuration_directory.join(format!("{}.yaml", environment.as_str()));
+    let override_file = configuration_directory.join("override.yaml");
+
+    let mut sources = vec![base_file.display().to_string()];
+    let mut builder = config::Config::builder()
+        .set_override("environment", environment.as_str())?
+        .add_source(config::File::from(base_file))
+        .add_source(config::File::from(environment_file.clone()));
+    sources.push(environment_file.display().to_string());
+    if override_file.exists() {
+        sources.push(override_file.display().to_string());
+        builder = builder.add_source(config::File::from(override_file));
+    }
+    builder = builder.add_source(
+        config::Environment::with_prefix("APP")
+            .prefix_separator("_")
+            .separator("__"),
+    );
+    // 只记录变量名，不记录变量值
+    sources.extend(
+        std::env::vars()
+            .map(|(name, _)| name)
+            .filter(|name| name.starts_with("APP_") && !name.ends_with("_FILE"))
+            .map(|name| format!("${}", name)),
+    );
+    for secret in secret_file_overrides(std::env::vars())? {
+        sources.push(format!("{} ({})", secret.path, secret.key));
+        builder = builder.set_override(secret.key, secret.value)?;
+    }
+
+    let mut settings = builder.build()?.try_deserialize::<Settings>()?;
+    settings.sources = sources;
+    Ok(settings)
+}
+
+/// 从 `APP_<KEY>_FILE` 环境变量读取到的密钥
+#[derive(Debug, PartialEq, Eq)]
+struct SecretFileOverride {
+    key: String,
+    path: String,
+    value: String,
+}
+
+/// 读取 `APP_<KEY>_FILE` 环境变量指向的文件，文件末尾的换行会被去掉
+fn secret_file_overrides(
+    vars: impl Iterator<Item = (String, String)>,
+) -> Result<Vec<SecretFileOverride>, config::ConfigError> {
+    vars.filter_map(|(name, path)| {
+        let key = name.strip_prefix("APP_")?.strip_suffix("_FILE")?;
+        Some((key.to_lowercase().replace("__", "."), path))
+    })
+    .map(|(key, path)| {
+        let value = std::fs::read_to_string(&path).map_err(|e| {
+            config::ConfigError::Message(format!(
+                "Failed to read the secret file {} for {}: {}",
+                path, key, e
+            ))
+        })?;
+        let value = value.trim_end_matches(['\r', '\n']).to_owned();
+        Ok(SecretFileOverride { key, path, value })
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::configuration::{
+        get_configuration, secret_file_overrides, DatabaseSettings, EmailClientSettings,
+        Environment, SecretFileOverride,
+    };
+    use claim::assert_err;
+    use sqlx::postgres::PgSslMode;
+
+    fn database_settings() -> DatabaseSettings {
+        config::Config::builder()
+            .add_source(config::File::with_name("configuration/base.yaml"))
+            .build()
+            .unwrap()
+            .get("database")
+            .unwrap()
+    }
+
+    fn email_client_settings() -> EmailClientSettings {
+        config::Config::builder()
+            .add_source(config::File::with_name("configuration/base.yaml"))
+            .build()
+            .unwrap()
+            .get("email_client")
+            .unwrap()
+    }
+
+    #[test]
+    fn the_configuration_sources_are_returned_with_the_settings() {
+        // 日志在读取配置之后才初始化，来源需要随配置一起返回，由调用方记录
+        let settings = get_configuration().unwrap();
+
+        let environment_file = format!("{}.yaml", settings.environment.as_str());
+        assert!(settings.sources[0].ends_with("base.yaml"));
+        assert!(settings.sources[1].ends_with(&environment_file));
+    }
+
+    #[test]
+    fn invalid_email_client_settings_are_reported_instead_of_panicking() {
+        let mut settings = email_client_settings();
+        settings.sender_email = "not-an-email".into();
+        assert!(settings.client().is_err());
+
+        let mut settings = email_client_settings();
+        settings.templates_directory = "does/not/exist".into();
+        assert!(settings.client().is_err());
+    }
+
+    #[test]
+    fn connect_options_are_built_from_the_settings() {
+        let settings = database_settings();
+
+        let options = settings.with_db();
+
+        assert_eq!(options.get_host(), settings.host);
+        assert_eq!(options.get_port(), settings.port);
+        assert_eq!(
+            options.get_database(),
+            Some(settings.database_name.as_str())
+        );
+        assert_eq!(options.get_application_name(), Some("actix-demo"));
+        assert!(matches!(options.get_ssl_mode(), PgSslMode::Prefer));
+        assert_eq!(settings.without_db().get_database(), None);
+    }
+
+    #[test]
+    fn ssl_is_required_and_verified_when_configured() {
+        let mut settings = database_settings();
+        settings.require_ssl = true;
+        assert!(matches!(
+            settings.with_db().get_ssl_mode(),
+            PgSslMode::Require
+        ));
+
+        settings.ssl_root_cert = Some("/etc/ssl/certs/ca.pem".into());
+        assert!(matches!(
+            settings.with_db().get_ssl_mode(),
+            PgSslMode::VerifyFull
+        ));
+    }
+
+    fn write_secret(contents: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn arbitrary_environment_names_are_accepted() {
+        for name in ["local", "Staging", "ci", "preview-wang_jian"] {
+            let environment = Environment::try_from(name.to_string()).unwrap();
+            assert_eq!(environment.as_str(), name.to_lowercase());
+        }
+    }
+
+    #[test]
+    fn unsafe_or_reserved_environment_names_are_rejected() {
+        for name in ["", "  ", "../secrets", "prod/eu", "base", "override"] {
+            assert_err!(Environment::try_from(name.to_string()));
+        }
+    }
+
+    #[test]
+    fn secret_files_override_the_matching_key() {
+        let path = write_secret("s3cr3t\n");
+        let vars = vec![(
+            "APP_DATABASE__PASSWORD_FILE".to_string(),
+            path.display().to_string(),
+        )];
+
+        let overrides = secret_file_overrides(vars.into_iter()).unwrap();
+
+        assert_eq!(
+            overrides,
+            vec![SecretFileOverride {
+                key: "database.password".to_string(),
+                path: path.display().to_string(),
+                value: "s3cr3t".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn unrelated_variables_are_ignored() {
+        let vars = vec![
+            ("APP_DATABASE__PASSWORD".to_string(), "password".to_string()),
+            ("PASSWORD_FILE".to_string(), "/does/not/exist".to_string()),
+        ];
+
+        let overrides = secret_file_overrides(vars.into_iter()).unwrap();
+
+        assert!(overrides.is_empty());
+    }
+
+    #[test]
+    fn a_missing_secret_file_is_an_error() {
+        let vars = vec![(
+            "APP_EMAIL_CLIENT__AUTHORIZATION_TOKEN_FILE".to_string(),
+            "/does/not/exist".to_string(),
+        )];
+
+        assert_err!(secret_file_overrides(vars.into_iter()));
+    }
 }
@@ -0,0 +1,464 @@
+use crate::configuration::{EmailTransportKind, Environment, Settings};
+use secrecy::{ExposeSecret, SecretBox};
+use std::net::ToSocketAddrs;
+use tracing_subscriber::EnvFilter;
+
+/// 签名 session cookie 的密钥至少需要 64 字节
+const MIN_HMAC_SECRET_LENGTH: usize = 64;
+
+/// `configuration/local.yaml` 中提交的开发用密钥，本地开发以外的环境不能使用
+const DEVELOPMENT_HMAC_SECRET: &str =
+    "super-long-and-secret-random-key-needed-to-verify-message-integrity";
+
+/// 回调接口的密码至少需要 32 字节
+const MIN_WEBHOOK_PASSWORD_LENGTH: usize = 32;
+
+/// `configuration/local.yaml` 中提交的开发用回调密码，本地开发以外的环境不能使用
+const DEVELOPMENT_WEBHOOK_PASSWORD: &str = "webhook-secret-for-local-development-only";
+
+/// 管理员密码的长度限制，与后台修改密码时的限制一致
+const MIN_ADMIN_PASSWORD_LENGTH: usize = 12;
+const MAX_ADMIN_PASSWORD_LENGTH: usize = 128;
+
+/// 单个配置项的问题
+#[derive(Debug, PartialEq, Eq)]
+pub struct SettingError {
+    pub key: &'static str,
+    pub message: String,
+}
+
+/// 配置校验失败，一次性列出所有有问题的配置项
+#[derive(thiserror::Error, Debug)]
+pub struct InvalidSettings(pub Vec<SettingError>);
+
+impl std::fmt::Display for InvalidSettings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid configuration:")?;
+        for e in &self.0 {
+            write!(f, "\n  - {}: {}", e.key, e.message)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Problems(Vec<SettingError>);
+
+impl Problems {
+    fn check(&mut self, key: &'static str, result: Result<(), String>) {
+        if let Err(message) = result {
+            self.0.push(SettingError { key, message });
+        }
+    }
+}
+
+impl Settings {
+    /// 在启动前检查所有配置项，避免在运行中途才因为配置错误而 panic
+    pub fn validate(&self) -> Result<(), InvalidSettings> {
+        let mut problems = Problems::default();
+        let application = &self.application;
+        problems.check(
+            "application.host",
+            resolvable(&application.host, application.port),
+        );
+        problems.check("application.base_url", http_url(&application.base_url));
+        problems.check(
+            "application.hmac_secret",
+            if application.hmac_secret.expose_secret().len() < MIN_HMAC_SECRET_LENGTH {
+                Err(format!(
+                    "must be at least {} bytes long",
+                    MIN_HMAC_SECRET_LENGTH
+                ))
+            } else {
+                not_a_development_secret(
+                    &self.environment,
+                    &application.hmac_secret,
+                    DEVELOPMENT_HMAC_SECRET,
+                )
+            },
+        );
+
+        let database = &self.database;
+        problems.check("database.port", non_zero(database.port.into()));
+        problems.check("database.host", resolvable(&database.host, database.port));
+        problems.check("database.database_name", non_empty(&database.database_name));
+        if let Some(ssl_root_cert) = &database.ssl_root_cert {
+            problems.check("database.ssl_root_cert", readable_file(ssl_root_cert));
+        }
+        problems.check(
+            "database.pool.max_connections",
+            if database.pool.max_connections == 0 {
+                Err("must be greater than zero".into())
+            } else if database.pool.max_connections < database.pool.min_connections {
+                Err("must not be smaller than min_connections".into())
+            } else {
+                Ok(())
+            },
+        );
+        problems.check(
+            "database.pool.acquire_timeout_milliseconds",
+            non_zero(database.pool.acquire_timeout_milliseconds),
+        );
+
+        let email_client = &self.email_client;
+        match email_client.transport {
+            EmailTransportKind::Postmark => {
+                problems.check("email_client.base_url", http_url(&email_client.base_url));
+            }
+            EmailTransportKind::Smtp => {
+                problems.check("email_client.smtp.host", non_empty(&email_client.smtp.host));
+                problems.check(
+                    "email_client.smtp.port",
+                    non_zero(email_client.smtp.port.into()),
+                );
+                problems.check(
+                    "email_client.smtp",
+                    email_client
+                        .transport()
+                        .map(|_| ())
+                        .map_err(|e| e.to_string()),
+                );
+            }
+            EmailTransportKind::File => {
+                problems.check(
+                    "email_client.output_directory",
+                    non_empty(&email_client.output_directory),
+                );
+            }
+            EmailTransportKind::Log => {}
+        }
+        problems.check(
+            "email_client.templates_directory",
+            email_client
+                .templates()
+                .map(|_| ())
+                .map_err(|e| format!("{:#}", e)),
+        );
+        problems.check(
+            "email_client.sender_email",
+            email_client.sender().map(|_| ()).map_err(|e| e.to_string()),
+        );
+        problems.check(
+            "email_client.timeout_milliseconds",
+            non_zero(email_client.timeout_milliseconds),
+        );
+
+        let delivery_worker = &self.delivery_worker;
+        problems.check(
+            "delivery_worker.base_backoff_milliseconds",
+            non_zero(delivery_worker.base_backoff_milliseconds),
+        );
+        problems.check(
+            "delivery_worker.max_backoff_milliseconds",
+            if delivery_worker.max_backoff_milliseconds < delivery_worker.base_backoff_milliseconds
+            {
+                Err("must not be smaller than base_backoff_milliseconds".into())
+            } else {
+                Ok(())
+            },
+        );
+
+        problems.check(
+            "health.timeout_milliseconds",
+            non_zero(self.health.timeout_milliseconds),
+        );
+
+        let webhooks = &self.webhooks;
+        problems.check("webhooks.username", non_empty(&webhooks.username));
+        problems.check(
+            "webhooks.password",
+            if webhooks.password.expose_secret().len() < MIN_WEBHOOK_PASSWORD_LENGTH {
+                Err(format!(
+                    "must be at least {} bytes long",
+                    MIN_WEBHOOK_PASSWORD_LENGTH
+                ))
+            } else {
+                not_a_development_secret(
+                    &self.environment,
+                    &webhooks.password,
+                    DEVELOPMENT_WEBHOOK_PASSWORD,
+                )
+            },
+        );
+
+        let telemetry = &self.telemetry;
+        if let Some(otlp_endpoint) = &telemetry.otlp_endpoint {
+            problems.check("telemetry.otlp_endpoint", http_url(otlp_endpoint));
+        }
+        problems.check(
+            "telemetry.otlp_timeout_milliseconds",
+            non_zero(telemetry.otlp_timeout_milliseconds),
+        );
+
+        if let Some(admin) = &self.admin {
+            problems.check("admin.username", non_empty(&admin.username));
+            problems.check(
+                "admin.password",
+                if (MIN_ADMIN_PASSWORD_LENGTH..=MAX_ADMIN_PASSWORD_LENGTH)
+                    .contains(&admin.password.expose_secret().len())
+                {
+                    Ok(())
+                } else {
+                    Err(format!(
+                        "must be between {} and {} characters long",
+                        MIN_ADMIN_PASSWORD_LENGTH, MAX_ADMIN_PASSWORD_LENGTH
+                    ))
+                },
+            );
+        }
+
+        let log = &self.log;
+        problems.check("log.filter", log_filter(&log.filter));
+        if let Some(file) = &log.file {
+            problems.check("log.file.directory", non_empty(&file.directory));
+            problems.check("log.file.prefix", non_empty(&file.prefix));
+            problems.check("log.file.max_files", non_zero(file.max_files as u64));
+        }
+
+        if problems.0.is_empty() {
+            Ok(())
+        } else {
+            Err(InvalidSettings(problems.0))
+        }
+    }
+}
+
+/// 仓库中提交的开发用密钥是公开的，只允许在本地开发环境使用
+fn not_a_development_secret(
+    environment: &Environment,
+    secret: &SecretBox<String>,
+    development_secret: &str,
+) -> Result<(), String> {
+    if !environment.is_local() && secret.expose_secret() == development_secret {
+        Err(format!(
+            "must not reuse the development value committed to the repository in the {} environment",
+            environment.as_str()
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+fn non_zero(value: u64) -> Result<(), String> {
+    if value == 0 {
+        Err("must be greater than zero".into())
+    } else {
+        Ok(())
+    }
+}
+
+fn non_empty(value: &str) -> Result<(), String> {
+    if value.trim().is_empty() {
+        Err("must not be empty".into())
+    } else {
+        Ok(())
+    }
+}
+
+fn readable_file(path: &str) -> Result<(), String> {
+    std::fs::File::open(path)
+        .map(|_| ())
+        .map_err(|e| format!("cannot read {}: {}", path, e))
+}
+
+fn http_url(value: &str) -> Result<(), String> {
+    let url =
+        reqwest::Url::parse(value).map_err(|e| format!("{} is not a valid URL: {}", value, e))?;
+    match url.scheme() {
+        "http" | "https" => Ok(()),
+        scheme => Err(format!("{} uses the unsupported scheme {}", value, scheme)),
+    }
+}
+
+fn log_filter(value: &str) -> Result<(), String> {
+    EnvFilter::try_new(value)
+        .map(|_| ())
+        .map_err(|e| format!("{:?} is not a valid filter: {}", value, e))
+}
+
+fn resolvable(host: &str, port: u16) -> Result<(), String> {
+    non_empty(host)?;
+    let mut addresses = (host, port)
+        .to_socket_addrs()
+        .map_err(|e| format!("{} cannot be resolved: {}", host, e))?;
+    match addresses.next() {
+        Some(_) => Ok(()),
+        None => Err(format!("{} does not resolve to any address", host)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::configuration::{
+        AdminSettings, EmailTransportKind, Environment, LogFileSettings, LogRotation, Settings,
+    };
+    use claim::assert_ok;
+    use secrecy::SecretBox;
+
+    fn settings() -> Settings {
+        config::Config::builder()
+            .add_source(config::File::with_name("configuration/base.yaml"))
+            .add_source(config::File::with_name("configuration/local.yaml"))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap()
+    }
+
+    #[test]
+    fn the_base_configuration_is_valid() {
+        assert_ok!(settings().validate());
+    }
+
+    #[test]
+    fn every_problem_is_reported_at_once() {
+        let mut settings = settings();
+        settings.application.base_url = "not a url".into();
+        settings.application.hmac_secret = SecretBox::new(Box::new("short".into()));
+        settings.email_client.sender_email = "not-an-email".into();
+        settings.email_client.timeout_milliseconds = 0;
+        settings.delivery_worker.max_backoff_milliseconds = 1;
+
+        let keys: Vec<_> = settings
+            .validate()
+            .unwrap_err()
+            .0
+            .into_iter()
+            .map(|e| e.key)
+            .collect();
+
+        assert_eq!(
+            keys,
+            vec![
+                "application.base_url",
+                "application.hmac_secret",
+                "email_client.sender_email",
+                "email_client.timeout_milliseconds",
+                "delivery_worker.max_backoff_milliseconds",
+            ]
+        );
+    }
+
+    #[test]
+    fn unresolvable_hosts_are_rejected() {
+        let mut settings = settings();
+        settings.database.host = "host.invalid".into();
+
+        let errors = settings.validate().unwrap_err().0;
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].key, "database.host");
+    }
+
+    #[test]
+    fn pool_and_tls_settings_are_checked() {
+        let mut settings = settings();
+        settings.database.ssl_root_cert = Some("/does/not/exist.pem".into());
+        settings.database.pool.min_connections = 5;
+        settings.database.pool.max_connections = 2;
+
+        let keys: Vec<_> = settings
+            .validate()
+            .unwrap_err()
+            .0
+            .into_iter()
+            .map(|e| e.key)
+            .collect();
+
+        assert_eq!(
+            keys,
+            vec!["database.ssl_root_cert", "database.pool.max_connections"]
+        );
+    }
+
+    #[test]
+    fn smtp_credentials_must_be_complete() {
+        let mut settings = settings();
+        settings.email_client.transport = EmailTransportKind::Smtp;
+        settings.email_client.smtp.username = Some("user".into());
+
+        let errors = settings.validate().unwrap_err().0;
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].key, "email_client.smtp");
+    }
+
+    #[test]
+    fn the_error_lists_every_key() {
+        let mut settings = settings();
+        settings.application.base_url = "ftp://example.com".into();
+        settings.health.timeout_milliseconds = 0;
+        settings.telemetry.otlp_endpoint = Some("127.0.0.1:4318".into());
+
+        let message = settings.validate().unwrap_err().to_string();
+
+        assert!(message.contains("application.base_url"), "{}", message);
+        assert!(
+            message.contains("health.timeout_milliseconds"),
+            "{}",
+            message
+        );
+        assert!(message.contains("telemetry.otlp_endpoint"), "{}", message);
+    }
+
+    #[test]
+    fn committed_secrets_are_rejected_outside_local_development() {
+        let mut settings = settings();
+        settings.environment = Environment::try_from("production".to_string()).unwrap();
+        settings.admin = None;
+
+        let keys: Vec<_> = settings
+            .validate()
+            .unwrap_err()
+            .0
+            .into_iter()
+            .map(|e| e.key)
+            .collect();
+
+        assert_eq!(keys, vec!["application.hmac_secret", "webhooks.password"]);
+    }
+
+    #[test]
+    fn admin_credentials_are_checked_when_configured() {
+        let mut settings = settings();
+        settings.admin = Some(AdminSettings {
+            username: " ".into(),
+            password: SecretBox::new(Box::new("short".into())),
+        });
+
+        let keys: Vec<_> = settings
+            .validate()
+            .unwrap_err()
+            .0
+            .into_iter()
+            .map(|e| e.key)
+            .collect();
+
+        assert_eq!(keys, vec!["admin.username", "admin.password"]);
+    }
+
+    #[test]
+    fn log_filter_and_file_settings_are_checked() {
+        let mut settings = settings();
+        settings.log.filter = "info,sqlx=loud".into();
+        settings.log.file = Some(LogFileSettings {
+            directory: "".into(),
+            prefix: "actix-demo.log".into(),
+            rotation: LogRotation::Daily,
+            max_files: 0,
+        });
+
+        let keys: Vec<_> = settings
+            .validate()
+            .unwrap_err()
+            .0
+            .into_iter()
+            .map(|e| e.key)
+            .collect();
+
+        assert_eq!(
+            keys,
+            vec!["log.filter", "log.file.directory", "log.file.max_files"]
+        );
+    }
+}
@@ -1,3 +1,5 @@
 pub mod new_subscriber;
 pub mod subscriber_email;
 pub mod subscriber_name;
+pub mod tracking_token;
+pub mod unsubscribe_token;
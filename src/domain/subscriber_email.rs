@@ -1,18 +1,39 @@
+use crate::telemetry::redact;
 use validator::ValidateEmail;
 
-#[derive(Debug)]
 pub struct SubscriberEmail(String);
 
+/// 订阅者邮箱校验失败的原因
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum SubscriberEmailError {
+    #[error("The email must not be empty.")]
+    Empty,
+    /// 不包含原值，避免校验失败的邮箱随错误信息写入日志
+    #[error("The email address is not valid.")]
+    Invalid,
+}
+
 impl SubscriberEmail {
-    pub fn parse(s: String) -> Result<Self, String> {
-        if ValidateEmail::validate_email(&s) {
+    pub fn parse(s: String) -> Result<Self, SubscriberEmailError> {
+        if s.trim().is_empty() {
+            Err(SubscriberEmailError::Empty)
+        } else if ValidateEmail::validate_email(&s) {
             Ok(SubscriberEmail(s))
         } else {
-            Err(format!("{} is not a valid subscriber email.", s))
+            Err(SubscriberEmailError::Invalid)
         }
     }
 }
 
+/// 邮箱经过脱敏，随 `{:?}` 写入日志或错误信息时不会泄露原值
+impl std::fmt::Debug for SubscriberEmail {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("SubscriberEmail")
+            .field(&format_args!("{}", redact(&self.0)))
+            .finish()
+    }
+}
+
 impl AsRef<str> for SubscriberEmail {
     fn as_ref(&self) -> &str {
         &self.0
@@ -22,6 +43,7 @@ impl AsRef<str> for SubscriberEmail {
 #[cfg(test)]
 mod tests {
     use crate::domain::subscriber_email::SubscriberEmail;
+    use crate::telemetry::redact;
     use claim::assert_err;
     use fake::faker::internet::en::SafeEmail;
     use fake::Fake;
@@ -33,7 +55,6 @@ mod tests {
     impl Arbitrary for ValidEmailFixture {
         fn arbitrary(_g: &mut Gen) -> ValidEmailFixture {
             let email = SafeEmail().fake::<String>();
-            println!("email: {}", &email);
             ValidEmailFixture(email)
         }
     }
@@ -60,4 +81,17 @@ mod tests {
         let email = "@wangjian.com".to_string();
         assert_err!(SubscriberEmail::parse(email));
     }
+
+    #[test]
+    fn debug_output_does_not_reveal_the_email() {
+        let email = SubscriberEmail::parse("wangjian@example.com".into()).unwrap();
+
+        let debug = format!("{:?}", email);
+
+        assert!(!debug.contains("wangjian@example.com"));
+        assert_eq!(
+            debug,
+            format!("SubscriberEmail({})", redact("wangjian@example.com"))
+        );
+    }
 }
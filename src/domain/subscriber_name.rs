@@ -1,16 +1,29 @@
 use unicode_segmentation::UnicodeSegmentation;
 
+const FORBIDDEN_CHARACTERS: [char; 9] = ['/', '(', ')', '"', '<', '>', '\\', '{', '}'];
+
 #[derive(Debug)]
 pub struct SubscriberName(String);
 
+/// 订阅者姓名校验失败的原因
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum SubscriberNameError {
+    #[error("The name must not be empty.")]
+    Empty,
+    #[error("The name must not be longer than 256 characters.")]
+    TooLong,
+    #[error("The name must not contain any of the characters / ( ) \" < > \\ {{ }}.")]
+    ForbiddenCharacters,
+}
+
 impl SubscriberName {
-    pub fn parse(s: String) -> Result<Self, String> {
-        let is_empty_or_whitespace = s.trim().is_empty();
-        let is_too_long = s.graphemes(true).count() > 256;
-        let forbidden_characters = ['/', '(', ')', '"', '<', '>', '\\', '{', '}'];
-        let contains_forbidden_characters = s.chars().any(|c| forbidden_characters.contains(&c));
-        if is_empty_or_whitespace || is_too_long || contains_forbidden_characters {
-            Err(format!("{} is not a valid subscriber name.", s))
+    pub fn parse(s: String) -> Result<Self, SubscriberNameError> {
+        if s.trim().is_empty() {
+            Err(SubscriberNameError::Empty)
+        } else if s.graphemes(true).count() > 256 {
+            Err(SubscriberNameError::TooLong)
+        } else if s.chars().any(|c| FORBIDDEN_CHARACTERS.contains(&c)) {
+            Err(SubscriberNameError::ForbiddenCharacters)
         } else {
             Ok(SubscriberName(s))
         }
@@ -25,7 +38,7 @@ impl AsRef<str> for SubscriberName {
 
 #[cfg(test)]
 mod tests {
-    use crate::domain::subscriber_name::SubscriberName;
+    use crate::domain::subscriber_name::{SubscriberName, SubscriberNameError};
     use claim::{assert_err, assert_ok};
 
     #[test]
@@ -60,6 +73,22 @@ mod tests {
         }
     }
 
+    #[test]
+    fn rejections_report_the_reason() {
+        assert_eq!(
+            SubscriberName::parse("".into()).unwrap_err(),
+            SubscriberNameError::Empty
+        );
+        assert_eq!(
+            SubscriberName::parse("a".repeat(257)).unwrap_err(),
+            SubscriberNameError::TooLong
+        );
+        assert_eq!(
+            SubscriberName::parse("<script>".into()).unwrap_err(),
+            SubscriberNameError::ForbiddenCharacters
+        );
+    }
+
     #[test]
     fn a_valid_name_is_parsed_successfully() {
         let name = "Wang Jian".to_string();
@@ -0,0 +1,303 @@
+use crate::telemetry::redact;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, SecretBox};
+use sha2::Sha256;
+use uuid::Uuid;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// 与退订令牌使用同一个密钥，加上前缀避免两种令牌的签名可以互相替换
+const DOMAIN: &[u8] = b"tracking:";
+
+/// 追踪令牌记录的行为
+#[derive(Debug, PartialEq, Eq)]
+pub enum TrackingTarget {
+    /// 邮件中的追踪像素被加载
+    Open,
+    /// 点击了邮件中的链接，附带原始地址
+    Click(String),
+}
+
+/// 从追踪令牌中解析出的事件
+#[derive(Debug, PartialEq, Eq)]
+pub struct TrackingEvent {
+    pub newsletter_issue_id: Uuid,
+    pub subscriber_id: Uuid,
+    pub target: TrackingTarget,
+}
+
+/// 追踪令牌：`<简报 id>.<订阅者 id>[.<原始链接>].<HMAC 签名>`，
+/// 签名保证 `/t/{token}` 不会被当作任意地址的跳转器使用
+#[derive(Debug)]
+pub struct TrackingToken(String);
+
+impl TrackingToken {
+    pub fn generate(
+        newsletter_issue_id: Uuid,
+        subscriber_id: Uuid,
+        url: Option<&str>,
+        secret: &SecretBox<String>,
+    ) -> Self {
+        let mut payload = format!(
+            "{}.{}",
+            newsletter_issue_id.simple(),
+            subscriber_id.simple()
+        );
+        if let Some(url) = url {
+            payload.push('.');
+            payload.push_str(&URL_SAFE_NO_PAD.encode(url));
+        }
+        let signature = URL_SAFE_NO_PAD.encode(mac(&payload, secret).finalize().into_bytes());
+        Self(format!("{}.{}", payload, signature))
+    }
+
+    /// 校验签名并返回令牌对应的事件
+    pub fn verify(token: &str, secret: &SecretBox<String>) -> Result<TrackingEvent, String> {
+        let invalid = || format!("{} is not a valid tracking token.", redact(token));
+        let (payload, signature) = token.rsplit_once('.').ok_or_else(invalid)?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).map_err(|_| invalid())?;
+        mac(payload, secret)
+            .verify_slice(&signature)
+            .map_err(|_| invalid())?;
+        let mut parts = payload.split('.');
+        let newsletter_issue_id = parts
+            .next()
+            .and_then(|id| Uuid::try_parse(id).ok())
+            .ok_or_else(invalid)?;
+        let subscriber_id = parts
+            .next()
+            .and_then(|id| Uuid::try_parse(id).ok())
+            .ok_or_else(invalid)?;
+        let target = match parts.next() {
+            None => TrackingTarget::Open,
+            Some(url) => {
+                let url = URL_SAFE_NO_PAD.decode(url).map_err(|_| invalid())?;
+                TrackingTarget::Click(String::from_utf8(url).map_err(|_| invalid())?)
+            }
+        };
+        if parts.next().is_some() {
+            return Err(invalid());
+        }
+        Ok(TrackingEvent {
+            newsletter_issue_id,
+            subscriber_id,
+            target,
+        })
+    }
+}
+
+fn mac(payload: &str, secret: &SecretBox<String>) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret.expose_secret().as_bytes())
+        .expect("HMAC can take a key of any size");
+    mac.update(DOMAIN);
+    mac.update(payload.as_bytes());
+    mac
+}
+
+impl AsRef<str> for TrackingToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+/// 为简报生成追踪像素与追踪链接
+pub struct TrackingLinks {
+    base_url: String,
+    secret: SecretBox<String>,
+}
+
+impl TrackingLinks {
+    pub fn new(base_url: String, secret: SecretBox<String>) -> Self {
+        Self { base_url, secret }
+    }
+
+    pub fn open_pixel(&self, newsletter_issue_id: Uuid, subscriber_id: Uuid) -> String {
+        self.url(TrackingToken::generate(
+            newsletter_issue_id,
+            subscriber_id,
+            None,
+            &self.secret,
+        ))
+    }
+
+    pub fn click(&self, newsletter_issue_id: Uuid, subscriber_id: Uuid, url: &str) -> String {
+        self.url(TrackingToken::generate(
+            newsletter_issue_id,
+            subscriber_id,
+            Some(url),
+            &self.secret,
+        ))
+    }
+
+    fn url(&self, token: TrackingToken) -> String {
+        format!("{}/t/{}", self.base_url, token.as_ref())
+    }
+
+    pub fn verify(&self, token: &str) -> Result<TrackingEvent, String> {
+        TrackingToken::verify(token, &self.secret)
+    }
+
+    /// 把 HTML 中所有指向 http(s) 地址的 `href` 改写为追踪链接，`mailto:` 与页内锚点保持不变
+    pub fn track_links(
+        &self,
+        html: &str,
+        newsletter_issue_id: Uuid,
+        subscriber_id: Uuid,
+    ) -> String {
+        // ASCII 小写不改变字节偏移，可以在小写副本中查找后直接切分原文
+        let lowercase = html.to_ascii_lowercase();
+        let mut tracked = String::with_capacity(html.len());
+        let mut copied = 0;
+        let mut position = 0;
+        while let Some(offset) = lowercase[position..].find("href=") {
+            let url_start = position + offset + "href=".len() + 1;
+            let quote = match html.as_bytes().get(url_start - 1) {
+                Some(b'"') => '"',
+                Some(b'\'') => '\'',
+                _ => {
+                    position = url_start - 1;
+                    continue;
+                }
+            };
+            let Some(length) = html[url_start..].find(quote) else {
+                break;
+            };
+            let url_end = url_start + length;
+            let url = unescape_html(&html[url_start..url_end]);
+            if url.starts_with("http://") || url.starts_with("https://") {
+                tracked.push_str(&html[copied..url_start]);
+                tracked.push_str(&self.click(newsletter_issue_id, subscriber_id, &url));
+                copied = url_end;
+            }
+            position = url_end;
+        }
+        tracked.push_str(&html[copied..]);
+        tracked
+    }
+}
+
+/// 属性值中的字符实体还原为原始字符，跳转时使用真实的地址
+fn unescape_html(s: &str) -> String {
+    s.replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::tracking_token::{TrackingLinks, TrackingTarget, TrackingToken};
+    use claim::{assert_err, assert_ok};
+    use secrecy::SecretBox;
+    use uuid::Uuid;
+
+    fn secret(s: &str) -> SecretBox<String> {
+        SecretBox::new(Box::new(s.to_string()))
+    }
+
+    fn links() -> TrackingLinks {
+        TrackingLinks::new("https://example.com".into(), secret("secret"))
+    }
+
+    /// 取出追踪链接中的令牌并校验
+    fn verify(links: &TrackingLinks, url: &str) -> TrackingTarget {
+        let token = url.strip_prefix("https://example.com/t/").unwrap();
+        links.verify(token).unwrap().target
+    }
+
+    #[test]
+    fn open_and_click_tokens_are_verified() {
+        let (issue_id, subscriber_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let open = TrackingToken::generate(issue_id, subscriber_id, None, &secret("secret"));
+        let click = TrackingToken::generate(
+            issue_id,
+            subscriber_id,
+            Some("https://example.com/a?b=c&d=e"),
+            &secret("secret"),
+        );
+
+        let open = TrackingToken::verify(open.as_ref(), &secret("secret")).unwrap();
+        let click = TrackingToken::verify(click.as_ref(), &secret("secret")).unwrap();
+
+        assert_eq!(open.newsletter_issue_id, issue_id);
+        assert_eq!(open.subscriber_id, subscriber_id);
+        assert_eq!(open.target, TrackingTarget::Open);
+        assert_eq!(
+            click.target,
+            TrackingTarget::Click("https://example.com/a?b=c&d=e".into())
+        );
+    }
+
+    #[test]
+    fn a_token_with_a_replaced_url_is_rejected() {
+        let token = TrackingToken::generate(
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Some("https://example.com"),
+            &secret("secret"),
+        );
+        let parts: Vec<_> = token.as_ref().split('.').collect();
+        let forged = format!(
+            "{}.{}.{}.{}",
+            parts[0],
+            parts[1],
+            base64::Engine::encode(
+                &base64::engine::general_purpose::URL_SAFE_NO_PAD,
+                "https://evil.example.com"
+            ),
+            parts[3]
+        );
+
+        assert_err!(TrackingToken::verify(&forged, &secret("secret")));
+        assert_ok!(TrackingToken::verify(token.as_ref(), &secret("secret")));
+    }
+
+    #[test]
+    fn a_token_signed_with_another_secret_is_rejected() {
+        let token = TrackingToken::generate(Uuid::new_v4(), Uuid::new_v4(), None, &secret("a"));
+        assert_err!(TrackingToken::verify(token.as_ref(), &secret("b")));
+    }
+
+    #[test]
+    fn malformed_tokens_are_rejected() {
+        for token in ["", "no-dot", "a.b", "a.b.c.d.e", "."] {
+            assert_err!(TrackingToken::verify(token, &secret("secret")));
+        }
+    }
+
+    #[test]
+    fn http_links_are_rewritten() {
+        let links = links();
+        let html = r#"<p><a href="https://example.org/a?x=1&amp;y=2">A</a> <A HREF='http://example.org/b'>B</A></p>"#;
+
+        let tracked = links.track_links(html, Uuid::new_v4(), Uuid::new_v4());
+
+        let urls: Vec<_> = tracked
+            .split(['"', '\''])
+            .filter(|s| s.starts_with("https://example.com/t/"))
+            .map(|url| verify(&links, url))
+            .collect();
+        assert_eq!(
+            urls,
+            vec![
+                TrackingTarget::Click("https://example.org/a?x=1&y=2".into()),
+                TrackingTarget::Click("http://example.org/b".into()),
+            ]
+        );
+        assert!(tracked.starts_with("<p><a href=\"https://example.com/t/"));
+        assert!(tracked.ends_with(">B</A></p>"));
+    }
+
+    #[test]
+    fn other_links_are_left_alone() {
+        let html = r##"<a href="mailto:editor@example.com">Mail</a><a href="#top">Top</a><a href=unquoted>X</a>"##;
+
+        let tracked = links().track_links(html, Uuid::new_v4(), Uuid::new_v4());
+
+        assert_eq!(tracked, html);
+    }
+}
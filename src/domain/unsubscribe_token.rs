@@ -0,0 +1,123 @@
+use crate::telemetry::redact;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, SecretBox};
+use sha2::Sha256;
+use uuid::Uuid;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// 退订令牌：`<订阅者 id>.<HMAC 签名>`，无需入库即可校验，且无法伪造他人的令牌
+#[derive(Debug)]
+pub struct UnsubscribeToken(String);
+
+impl UnsubscribeToken {
+    pub fn generate(subscriber_id: Uuid, secret: &SecretBox<String>) -> Self {
+        let signature = URL_SAFE_NO_PAD.encode(mac(subscriber_id, secret).finalize().into_bytes());
+        Self(format!("{}.{}", subscriber_id.simple(), signature))
+    }
+
+    /// 校验签名并返回令牌对应的订阅者 id
+    pub fn verify(token: &str, secret: &SecretBox<String>) -> Result<Uuid, String> {
+        let invalid = || format!("{} is not a valid unsubscribe token.", redact(token));
+        let (subscriber_id, signature) = token.split_once('.').ok_or_else(invalid)?;
+        let subscriber_id = Uuid::try_parse(subscriber_id).map_err(|_| invalid())?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).map_err(|_| invalid())?;
+        mac(subscriber_id, secret)
+            .verify_slice(&signature)
+            .map_err(|_| invalid())?;
+        Ok(subscriber_id)
+    }
+}
+
+fn mac(subscriber_id: Uuid, secret: &SecretBox<String>) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret.expose_secret().as_bytes())
+        .expect("HMAC can take a key of any size");
+    mac.update(subscriber_id.as_bytes());
+    mac
+}
+
+impl AsRef<str> for UnsubscribeToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+/// 为订阅者生成退订、关闭追踪等管理订阅的链接
+pub struct UnsubscribeLinks {
+    base_url: String,
+    secret: SecretBox<String>,
+}
+
+impl UnsubscribeLinks {
+    pub fn new(base_url: String, secret: SecretBox<String>) -> Self {
+        Self { base_url, secret }
+    }
+
+    pub fn for_subscriber(&self, subscriber_id: Uuid) -> String {
+        let token = UnsubscribeToken::generate(subscriber_id, &self.secret);
+        format!(
+            "{}/subscriptions/unsubscribe?token={}",
+            self.base_url,
+            token.as_ref()
+        )
+    }
+
+    /// 关闭打开与点击追踪的链接，与退订链接使用同一个令牌
+    pub fn tracking_opt_out_for_subscriber(&self, subscriber_id: Uuid) -> String {
+        let token = UnsubscribeToken::generate(subscriber_id, &self.secret);
+        format!(
+            "{}/subscriptions/tracking_opt_out?token={}",
+            self.base_url,
+            token.as_ref()
+        )
+    }
+
+    pub fn verify(&self, token: &str) -> Result<Uuid, String> {
+        UnsubscribeToken::verify(token, &self.secret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::unsubscribe_token::UnsubscribeToken;
+    use claim::{assert_err, assert_ok_eq};
+    use secrecy::SecretBox;
+    use uuid::Uuid;
+
+    fn secret(s: &str) -> SecretBox<String> {
+        SecretBox::new(Box::new(s.to_string()))
+    }
+
+    #[test]
+    fn a_generated_token_is_verified() {
+        let subscriber_id = Uuid::new_v4();
+        let token = UnsubscribeToken::generate(subscriber_id, &secret("secret"));
+        assert_ok_eq!(
+            UnsubscribeToken::verify(token.as_ref(), &secret("secret")),
+            subscriber_id
+        );
+    }
+
+    #[test]
+    fn a_token_signed_with_another_secret_is_rejected() {
+        let token = UnsubscribeToken::generate(Uuid::new_v4(), &secret("secret"));
+        assert_err!(UnsubscribeToken::verify(token.as_ref(), &secret("other")));
+    }
+
+    #[test]
+    fn a_token_for_another_subscriber_is_rejected() {
+        let token = UnsubscribeToken::generate(Uuid::new_v4(), &secret("secret"));
+        let (_, signature) = token.as_ref().split_once('.').unwrap();
+        let forged = format!("{}.{}", Uuid::new_v4().simple(), signature);
+        assert_err!(UnsubscribeToken::verify(&forged, &secret("secret")));
+    }
+
+    #[test]
+    fn malformed_tokens_are_rejected() {
+        for token in ["", "no-dot", "not-a-uuid.c2ln", "."] {
+            assert_err!(UnsubscribeToken::verify(token, &secret("secret")));
+        }
+    }
+}
@@ -1,71 +1,368 @@
-use std::time::Duration;
+mod file;
+mod postmark;
+mod smtp;
+mod templates;
+
+pub use file::{FileTransport, LogTransport};
+pub use postmark::{PostmarkTransport, MAX_BATCH_SIZE};
+pub use smtp::{SmtpTls, SmtpTransport};
+pub use templates::{EmailTemplate, EmailTemplates, RenderedEmail};
 
 use crate::domain::subscriber_email::SubscriberEmail;
-use reqwest::Client;
-use secrecy::{ExposeSecret, SecretBox};
+use crate::metrics::{record_email_batch, record_email_send};
+use anyhow::Context;
+use lettre::message::header::{HeaderName, HeaderValue};
+use lettre::message::MultiPart;
+use std::future::Future;
+use std::time::Instant;
+
+/// 一封待发送的邮件，与具体的发送通道无关
+#[derive(Debug, Clone)]
+pub struct EmailMessage {
+    pub from: String,
+    pub to: String,
+    pub subject: String,
+    pub html_body: String,
+    pub text_body: String,
+    /// 额外的邮件头，例如 `List-Unsubscribe`
+    pub headers: Vec<(String, String)>,
+}
+
+impl EmailMessage {
+    /// 转换为 RFC 5322 格式的 MIME 邮件，供 SMTP 与文件通道使用
+    fn to_mime(&self) -> Result<lettre::Message, anyhow::Error> {
+        let mut builder = lettre::Message::builder()
+            .from(self.from.parse().context("Invalid sender address.")?)
+            .to(self.to.parse().context("Invalid recipient address.")?)
+            .subject(&self.subject);
+        for (name, value) in &self.headers {
+            let name = HeaderName::new_from_ascii(name.clone())
+                .with_context(|| format!("Invalid header name {}.", name))?;
+            builder = builder.raw_header(HeaderValue::new(name, value.clone()));
+        }
+        builder
+            .multipart(MultiPart::alternative_plain_html(
+                self.text_body.clone(),
+                self.html_body.clone(),
+            ))
+            .context("Failed to build the MIME message.")
+    }
+}
+
+/// 发送成功后服务商返回的信息
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SentEmail {
+    /// 服务商分配的邮件 ID，用于和退信等回调事件关联；不是所有通道都会提供
+    pub message_id: Option<String>,
+}
+
+/// 邮件服务商在响应体中返回的错误
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProviderError {
+    pub error_code: i64,
+    pub message: String,
+}
+
+impl std::fmt::Display for ProviderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (error code {})", self.message, self.error_code)
+    }
+}
+
+/// 发送失败的原因，决定投递任务是否值得重试
+#[derive(thiserror::Error, Debug)]
+pub enum EmailError {
+    /// 收件人此前退信、投诉或被手动停用，服务商拒绝继续投递
+    #[error("The recipient is inactive: {0}")]
+    InactiveRecipient(ProviderError),
+    /// 发件人签名不存在或尚未验证，需要运维人员处理
+    #[error("The sender signature is invalid: {0}")]
+    InvalidSender(ProviderError),
+    /// 被服务商限流
+    #[error("Rate limited by the email provider: {0}")]
+    RateLimited(ProviderError),
+    /// 服务商内部错误
+    #[error("The email provider failed: {0}")]
+    ServerError(ProviderError),
+    /// 服务商因其他原因拒收了这封邮件
+    #[error("The email provider rejected the message: {0}")]
+    Rejected(ProviderError),
+    /// 超时、连接失败等，稍后重试可能成功
+    #[error("Transient email delivery failure: {0:#}")]
+    Transient(anyhow::Error),
+    /// 收件人或邮件本身有问题，重试没有意义
+    #[error("Permanent email delivery failure: {0:#}")]
+    Permanent(anyhow::Error),
+}
+
+impl EmailError {
+    /// 稍后重试是否可能成功
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            EmailError::RateLimited(_) | EmailError::ServerError(_) | EmailError::Transient(_)
+        )
+    }
+
+    /// 批量请求整体失败时，为这一批中的每封邮件生成同类的错误
+    fn duplicate(&self) -> Self {
+        match self {
+            EmailError::InactiveRecipient(e) => EmailError::InactiveRecipient(e.clone()),
+            EmailError::InvalidSender(e) => EmailError::InvalidSender(e.clone()),
+            EmailError::RateLimited(e) => EmailError::RateLimited(e.clone()),
+            EmailError::ServerError(e) => EmailError::ServerError(e.clone()),
+            EmailError::Rejected(e) => EmailError::Rejected(e.clone()),
+            EmailError::Transient(e) => EmailError::Transient(anyhow::anyhow!("{:#}", e)),
+            EmailError::Permanent(e) => EmailError::Permanent(anyhow::anyhow!("{:#}", e)),
+        }
+    }
+}
+
+/// 邮件发送通道
+pub trait EmailTransport: Sync {
+    fn send(
+        &self,
+        message: &EmailMessage,
+    ) -> impl Future<Output = Result<SentEmail, EmailError>> + Send;
+
+    /// 发送一批邮件，按顺序返回每封邮件各自的结果；默认逐封发送
+    fn send_batch(
+        &self,
+        messages: &[EmailMessage],
+    ) -> impl Future<Output = Vec<Result<SentEmail, EmailError>>> + Send {
+        async move {
+            let mut outcomes = Vec::with_capacity(messages.len());
+            for message in messages {
+                outcomes.push(self.send(message).await);
+            }
+            outcomes
+        }
+    }
+
+    /// 检查通道当前是否可用，供就绪探针使用
+    fn ping(&self) -> impl Future<Output = Result<(), anyhow::Error>> + Send;
+}
+
+/// 按 `email_client.transport` 配置选择的发送通道
+pub enum EmailBackend {
+    Postmark(PostmarkTransport),
+    Smtp(SmtpTransport),
+    File(FileTransport),
+    Log(LogTransport),
+}
+
+impl EmailTransport for EmailBackend {
+    async fn send(&self, message: &EmailMessage) -> Result<SentEmail, EmailError> {
+        match self {
+            EmailBackend::Postmark(transport) => transport.send(message).await,
+            EmailBackend::Smtp(transport) => transport.send(message).await,
+            EmailBackend::File(transport) => transport.send(message).await,
+            EmailBackend::Log(transport) => transport.send(message).await,
+        }
+    }
+
+    async fn send_batch(&self, messages: &[EmailMessage]) -> Vec<Result<SentEmail, EmailError>> {
+        match self {
+            EmailBackend::Postmark(transport) => transport.send_batch(messages).await,
+            EmailBackend::Smtp(transport) => transport.send_batch(messages).await,
+            EmailBackend::File(transport) => transport.send_batch(messages).await,
+            EmailBackend::Log(transport) => transport.send_batch(messages).await,
+        }
+    }
+
+    async fn ping(&self) -> Result<(), anyhow::Error> {
+        match self {
+            EmailBackend::Postmark(transport) => transport.ping().await,
+            EmailBackend::Smtp(transport) => transport.ping().await,
+            EmailBackend::File(transport) => transport.ping().await,
+            EmailBackend::Log(transport) => transport.ping().await,
+        }
+    }
+}
+
+/// 批量发送中的一个收件人
+#[derive(Debug)]
+pub struct Recipient {
+    pub email: SubscriberEmail,
+    pub unsubscribe_url: String,
+}
+
+/// 批量发送中的一封模板邮件，每封邮件的主题与渲染上下文可以各不相同
+#[derive(Debug)]
+pub struct TemplateEmail<C> {
+    pub recipient: SubscriberEmail,
+    pub subject: String,
+    pub context: C,
+    pub unsubscribe_url: String,
+}
 
 pub struct EmailClient {
-    http_client: Client,
-    base_url: String,
     sender: SubscriberEmail,
-    authorization_token: SecretBox<String>,
+    transport: EmailBackend,
+    templates: EmailTemplates,
 }
 
 impl EmailClient {
     pub fn new(
-        base_url: String,
         sender: SubscriberEmail,
-        authorization_token: SecretBox<String>,
-        timeout: Duration,
+        transport: EmailBackend,
+        templates: EmailTemplates,
     ) -> Self {
         Self {
-            http_client: Client::builder().timeout(timeout).build().unwrap(),
-            base_url,
             sender,
-            authorization_token,
+            transport,
+            templates,
         }
     }
 
+    /// 探测发送通道是否可用
+    pub async fn ping(&self) -> Result<(), anyhow::Error> {
+        self.transport.ping().await
+    }
+
     pub async fn send_email(
         &self,
-        recipient: SubscriberEmail,
+        recipient: &SubscriberEmail,
         subject: &str,
         html_content: &str,
         text_content: &str,
-    ) -> Result<(), reqwest::Error> {
-        let url = format!("{}/email", self.base_url);
-        let request_body = SendEmailRequest {
-            from: self.sender.as_ref(),
-            to: recipient.as_ref(),
-            subject: subject,
-            html_body: html_content,
-            text_body: text_content,
-        };
+        unsubscribe_url: &str,
+    ) -> Result<SentEmail, EmailError> {
+        let message = self.message(
+            recipient,
+            subject,
+            html_content,
+            text_content,
+            unsubscribe_url,
+        );
+        let start = Instant::now();
+        let outcome = self.transport.send(&message).await;
+        record_email_send(&outcome, start.elapsed());
+        outcome
+    }
 
-        println!("{:?}", &request_body);
+    /// 用指定的模板渲染正文后发送
+    pub async fn send_template(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        template: EmailTemplate,
+        context: impl serde::Serialize,
+        unsubscribe_url: &str,
+    ) -> Result<SentEmail, EmailError> {
+        let rendered = self
+            .templates
+            .render(template, context)
+            .map_err(EmailError::Permanent)?;
+        self.send_email(
+            recipient,
+            subject,
+            &rendered.html,
+            &rendered.text,
+            unsubscribe_url,
+        )
+        .await
+    }
 
-        self.http_client
-            .post(&url)
-            .header(
-                "X-Postmark-Server-Token",
-                self.authorization_token.expose_secret(),
-            )
-            .json(&request_body)
-            .send()
-            .await?
-            .error_for_status()?;
-        Ok(())
+    /// 向多个收件人发送同一封邮件，按 `recipients` 的顺序返回每个收件人的结果，
+    /// 调用方只需要重试失败的收件人
+    pub async fn send_batch(
+        &self,
+        recipients: &[Recipient],
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Vec<Result<SentEmail, EmailError>> {
+        let messages: Vec<_> = recipients
+            .iter()
+            .map(|r| {
+                self.message(
+                    &r.email,
+                    subject,
+                    html_content,
+                    text_content,
+                    &r.unsubscribe_url,
+                )
+            })
+            .collect();
+        let start = Instant::now();
+        let outcomes = self.transport.send_batch(&messages).await;
+        record_email_batch(&outcomes, start.elapsed());
+        outcomes
+    }
+
+    /// 用指定的模板为每封邮件渲染正文后批量发送，按 `emails` 的顺序返回每封邮件的结果；
+    /// 渲染失败的邮件不会发送，直接以永久失败返回
+    pub async fn send_template_batch<C: serde::Serialize>(
+        &self,
+        template: EmailTemplate,
+        emails: &[TemplateEmail<C>],
+    ) -> Vec<Result<SentEmail, EmailError>> {
+        let mut rendered = Vec::with_capacity(emails.len());
+        let mut messages = Vec::with_capacity(emails.len());
+        for email in emails {
+            match self.templates.render(template, &email.context) {
+                Ok(body) => {
+                    messages.push(self.message(
+                        &email.recipient,
+                        &email.subject,
+                        &body.html,
+                        &body.text,
+                        &email.unsubscribe_url,
+                    ));
+                    rendered.push(Ok(()));
+                }
+                Err(e) => rendered.push(Err(EmailError::Permanent(e))),
+            }
+        }
+        let mut outcomes = Vec::new().into_iter();
+        if !messages.is_empty() {
+            let start = Instant::now();
+            let sent = self.transport.send_batch(&messages).await;
+            record_email_batch(&sent, start.elapsed());
+            outcomes = sent.into_iter();
+        }
+        rendered
+            .into_iter()
+            .map(|r| {
+                r.and_then(|()| {
+                    outcomes.next().unwrap_or_else(|| {
+                        Err(EmailError::Transient(anyhow::anyhow!(
+                            "The transport did not report the outcome of the message."
+                        )))
+                    })
+                })
+            })
+            .collect()
     }
-}
 
-#[derive(serde::Serialize, Debug)]
-#[serde(rename_all = "PascalCase")]
-struct SendEmailRequest<'a> {
-    from: &'a str,
-    to: &'a str,
-    subject: &'a str,
-    html_body: &'a str,
-    text_body: &'a str,
+    fn message(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        unsubscribe_url: &str,
+    ) -> EmailMessage {
+        EmailMessage {
+            from: self.sender.as_ref().to_owned(),
+            to: recipient.as_ref().to_owned(),
+            subject: subject.to_owned(),
+            html_body: html_content.to_owned(),
+            text_body: text_content.to_owned(),
+            // RFC 8058：邮件客户端可以直接 POST 该地址完成一键退订
+            headers: vec![
+                (
+                    "List-Unsubscribe".to_owned(),
+                    format!("<{}>", unsubscribe_url),
+                ),
+                (
+                    "List-Unsubscribe-Post".to_owned(),
+                    "List-Unsubscribe=One-Click".to_owned(),
+                ),
+            ],
+        }
+    }
 }
 
 #[cfg(test)]
@@ -73,7 +370,10 @@ mod tests {
     use std::time::Duration;
 
     use crate::domain::subscriber_email::SubscriberEmail;
-    use crate::email_client::EmailClient;
+    use crate::email_client::{
+        EmailBackend, EmailClient, EmailError, EmailTemplate, EmailTemplates, PostmarkTransport,
+        Recipient, TemplateEmail,
+    };
     use claim::assert_err;
     use claim::assert_ok;
     use fake::faker::internet::en::SafeEmail;
@@ -105,7 +405,13 @@ mod tests {
             .await;
 
         let _ = email_client
-            .send_email(email(), &subject(), &content(), &content())
+            .send_email(
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+                &unsubscribe_url(),
+            )
             .await;
     }
 
@@ -121,7 +427,13 @@ mod tests {
             .await;
 
         let outcome = email_client
-            .send_email(email(), &subject(), &content(), &content())
+            .send_email(
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+                &unsubscribe_url(),
+            )
             .await;
 
         assert_ok!(outcome);
@@ -139,7 +451,13 @@ mod tests {
             .await;
 
         let outcome = email_client
-            .send_email(email(), &subject(), &content(), &content())
+            .send_email(
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+                &unsubscribe_url(),
+            )
             .await;
 
         assert_err!(outcome);
@@ -158,7 +476,13 @@ mod tests {
             .await;
 
         let outcome = email_client
-            .send_email(email(), &subject(), &content(), &content())
+            .send_email(
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+                &unsubscribe_url(),
+            )
             .await;
 
         assert_err!(outcome);
@@ -180,8 +504,138 @@ mod tests {
             .await;
 
         let _ = email_client
-            .send_email(email(), &subject(), &content(), &content())
+            .send_email(
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+                &unsubscribe_url(),
+            )
+            .await;
+    }
+
+    #[tokio::test]
+    async fn send_batch_sends_one_request_for_all_recipients() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let recipients: Vec<_> = (0..3)
+            .map(|_| Recipient {
+                email: email(),
+                unsubscribe_url: unsubscribe_url(),
+            })
+            .collect();
+
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(method("POST"))
+            .and(path("/email/batch"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+                { "ErrorCode": 0, "Message": "OK" },
+                { "ErrorCode": 406, "Message": "Inactive recipient" },
+                { "ErrorCode": 0, "Message": "OK" },
+            ])))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcomes = email_client
+            .send_batch(&recipients, &subject(), &content(), &content())
+            .await;
+
+        assert_ok!(&outcomes[0]);
+        assert_err!(&outcomes[1]);
+        assert_ok!(&outcomes[2]);
+    }
+
+    #[tokio::test]
+    async fn send_template_sends_the_rendered_html_and_text_bodies() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(method("POST"))
+            .and(path("/email"))
+            .and(SendEmailBodyMatcher)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_template(
+                &email(),
+                &subject(),
+                EmailTemplate::Confirmation,
+                minijinja::context! { confirmation_link => "https://example.com/confirm" },
+                &unsubscribe_url(),
+            )
             .await;
+
+        assert_ok!(outcome);
+        let request = &mock_server.received_requests().await.unwrap()[0];
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        assert!(body["HtmlBody"]
+            .as_str()
+            .unwrap()
+            .contains(r#"<a href="https://example.com/confirm">here</a>"#));
+        assert!(body["TextBody"]
+            .as_str()
+            .unwrap()
+            .contains("Visit https://example.com/confirm to confirm"));
+    }
+
+    #[tokio::test]
+    async fn send_template_batch_renders_every_email_and_skips_the_ones_that_fail() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let template_email = |link: Option<&str>| TemplateEmail {
+            recipient: email(),
+            subject: subject(),
+            // 缺少 `confirmation_link` 时渲染失败
+            context: link.map(|link| minijinja::context! { confirmation_link => link }),
+            unsubscribe_url: unsubscribe_url(),
+        };
+        let emails = vec![
+            template_email(Some("https://example.com/confirm?first")),
+            template_email(None),
+            template_email(Some("https://example.com/confirm?third")),
+        ];
+
+        Mock::given(method("POST"))
+            .and(path("/email/batch"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+                { "ErrorCode": 0, "Message": "OK", "MessageID": "first" },
+                { "ErrorCode": 0, "Message": "OK", "MessageID": "third" },
+            ])))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcomes = email_client
+            .send_template_batch(EmailTemplate::Confirmation, &emails)
+            .await;
+
+        assert_eq!(outcomes.len(), 3);
+        assert_eq!(
+            outcomes[0].as_ref().unwrap().message_id.as_deref(),
+            Some("first")
+        );
+        assert!(matches!(outcomes[1], Err(EmailError::Permanent(_))));
+        assert_eq!(
+            outcomes[2].as_ref().unwrap().message_id.as_deref(),
+            Some("third")
+        );
+        let request = &mock_server.received_requests().await.unwrap()[0];
+        let body: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(body.len(), 2);
+        assert_eq!(body[0]["To"], emails[0].recipient.as_ref());
+        assert!(body[0]["HtmlBody"]
+            .as_str()
+            .unwrap()
+            .contains("confirm?first"));
+        assert_eq!(body[1]["To"], emails[2].recipient.as_ref());
+        assert!(body[1]["HtmlBody"]
+            .as_str()
+            .unwrap()
+            .contains("confirm?third"));
     }
 
     /// 生成随机邮件主题
@@ -194,6 +648,11 @@ mod tests {
         Paragraph(1..2).fake::<String>()
     }
 
+    /// 生成退订链接
+    fn unsubscribe_url() -> String {
+        "https://example.com/subscriptions/unsubscribe?token=abc".to_string()
+    }
+
     /// 生成随机订阅者邮件地址
     fn email() -> SubscriberEmail {
         SubscriberEmail::parse(SafeEmail().fake::<String>()).unwrap()
@@ -202,10 +661,16 @@ mod tests {
     /// 获取`EmailClient`实例
     fn email_client(base_url: String) -> EmailClient {
         EmailClient::new(
-            base_url,
             email(),
-            SecretBox::new(Faker.fake()),
-            Duration::from_millis(200),
+            EmailBackend::Postmark(
+                PostmarkTransport::new(
+                    base_url,
+                    SecretBox::new(Faker.fake()),
+                    Duration::from_millis(200),
+                )
+                .unwrap(),
+            ),
+            EmailTemplates::load("templates/email").unwrap(),
         )
     }
 
@@ -215,12 +680,24 @@ mod tests {
         fn matches(&self, request: &wiremock::Request) -> bool {
             let result: Result<serde_json::Value, _> = serde_json::from_slice(&request.body);
             if let Ok(body) = result {
-                dbg!(&body);
                 body.get("From").is_some()
                     && body.get("To").is_some()
                     && body.get("Subject").is_some()
                     && body.get("HtmlBody").is_some()
                     && body.get("TextBody").is_some()
+                    && body["Headers"]
+                        .as_array()
+                        .map(|headers| {
+                            headers.iter().any(|h| {
+                                h["Name"] == "List-Unsubscribe"
+                                    && h["Value"]
+                                        == "<https://example.com/subscriptions/unsubscribe?token=abc>"
+                            }) && headers.iter().any(|h| {
+                                h["Name"] == "List-Unsubscribe-Post"
+                                    && h["Value"] == "List-Unsubscribe=One-Click"
+                            })
+                        })
+                        .unwrap_or(false)
             } else {
                 false
             }
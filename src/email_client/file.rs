@@ -0,0 +1,98 @@
+use crate::email_client::{EmailError, EmailMessage, EmailTransport, SentEmail};
+use crate::telemetry::{redact, spawn_blocking_with_tracing};
+use anyhow::Context;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+/// 开发环境使用：把邮件写成 `.eml` 文件而不真正发送，可以直接用邮件客户端打开
+pub struct FileTransport {
+    directory: PathBuf,
+}
+
+impl FileTransport {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+        }
+    }
+}
+
+impl EmailTransport for FileTransport {
+    async fn send(&self, message: &EmailMessage) -> Result<SentEmail, EmailError> {
+        let mime = message.to_mime().map_err(EmailError::Permanent)?;
+        let path = self.directory.join(format!("{}.eml", Uuid::new_v4()));
+        let directory = self.directory.clone();
+        spawn_blocking_with_tracing(move || {
+            std::fs::create_dir_all(&directory)?;
+            std::fs::write(&path, mime.formatted())?;
+            tracing::info!(path = %path.display(), "Wrote email to a file instead of sending it.");
+            Ok::<_, std::io::Error>(())
+        })
+        .await
+        .context("Failed to spawn blocking task.")
+        .map_err(EmailError::Transient)?
+        .context("Failed to write the email to disk.")
+        .map_err(EmailError::Transient)?;
+        Ok(SentEmail::default())
+    }
+
+    async fn ping(&self) -> Result<(), anyhow::Error> {
+        let directory = self.directory.clone();
+        spawn_blocking_with_tracing(move || std::fs::create_dir_all(directory)).await??;
+        Ok(())
+    }
+}
+
+/// 开发环境使用：只把邮件内容写入日志
+pub struct LogTransport;
+
+impl EmailTransport for LogTransport {
+    async fn send(&self, message: &EmailMessage) -> Result<SentEmail, EmailError> {
+        tracing::info!(
+            to = %redact(&message.to),
+            subject = %message.subject,
+            text_body = %message.text_body,
+            "Logged email instead of sending it."
+        );
+        Ok(SentEmail::default())
+    }
+
+    async fn ping(&self) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::email_client::{EmailMessage, EmailTransport, FileTransport};
+    use claim::assert_ok;
+
+    #[tokio::test]
+    async fn messages_are_written_as_eml_files() {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let transport = FileTransport::new(&directory);
+        let message = EmailMessage {
+            from: "sender@example.com".into(),
+            to: "recipient@example.com".into(),
+            subject: "Hello".into(),
+            html_body: "<p>Hello</p>".into(),
+            text_body: "Hello".into(),
+            headers: vec![(
+                "List-Unsubscribe".into(),
+                "<https://example.com/unsubscribe>".into(),
+            )],
+        };
+
+        assert_ok!(transport.send(&message).await);
+
+        let files: Vec<_> = std::fs::read_dir(&directory).unwrap().collect();
+        assert_eq!(files.len(), 1);
+        let path = files[0].as_ref().unwrap().path();
+        assert_eq!(path.extension().unwrap(), "eml");
+        let eml = std::fs::read_to_string(path).unwrap();
+        assert!(eml.contains("To: recipient@example.com"));
+        assert!(eml.contains("Subject: Hello"));
+        assert!(eml.contains("List-Unsubscribe: <https://example.com/unsubscribe>"));
+        assert!(eml.contains("multipart/alternative"));
+    }
+}
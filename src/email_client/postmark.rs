@@ -0,0 +1,518 @@
+use crate::email_client::{EmailError, EmailMessage, EmailTransport, ProviderError, SentEmail};
+use crate::telemetry::{redact, redact_emails, trace_context_headers};
+use anyhow::Context;
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+use secrecy::{ExposeSecret, SecretBox};
+use std::time::Duration;
+
+/// Postmark 单次批量请求最多包含的邮件数
+pub const MAX_BATCH_SIZE: usize = 500;
+
+/// Postmark 的 API 错误码，见 <https://postmarkapp.com/developer/api/overview#error-codes>
+const SENDER_SIGNATURE_NOT_FOUND: i64 = 400;
+const SENDER_SIGNATURE_NOT_CONFIRMED: i64 = 401;
+const INACTIVE_RECIPIENT: i64 = 406;
+
+/// 通过 Postmark HTTP API 发送邮件
+pub struct PostmarkTransport {
+    http_client: Client,
+    base_url: String,
+    authorization_token: SecretBox<String>,
+}
+
+impl PostmarkTransport {
+    pub fn new(
+        base_url: String,
+        authorization_token: SecretBox<String>,
+        timeout: Duration,
+    ) -> Result<Self, anyhow::Error> {
+        let http_client = Client::builder()
+            .timeout(timeout)
+            .build()
+            .context("Failed to build the Postmark HTTP client.")?;
+        Ok(Self {
+            http_client,
+            base_url,
+            authorization_token,
+        })
+    }
+}
+
+impl EmailTransport for PostmarkTransport {
+    #[tracing::instrument(
+        name = "Send an email through Postmark",
+        skip_all,
+        fields(recipient = %redact(&message.to))
+    )]
+    async fn send(&self, message: &EmailMessage) -> Result<SentEmail, EmailError> {
+        let url = format!("{}/email", self.base_url);
+        let request_body = SendEmailRequest::from(message);
+        let response = self
+            .post(&url)
+            .json(&request_body)
+            .send()
+            .await
+            .map_err(|e| EmailError::Transient(e.into()))?;
+        let response = check_status(response).await?;
+        // 邮件已被接受，即使响应体无法解析也不能当作失败，否则会导致重复发送
+        let message_id = response
+            .json::<PostmarkResponse>()
+            .await
+            .ok()
+            .and_then(|r| r.message_id);
+        Ok(SentEmail { message_id })
+    }
+
+    async fn send_batch(&self, messages: &[EmailMessage]) -> Vec<Result<SentEmail, EmailError>> {
+        let mut outcomes = Vec::with_capacity(messages.len());
+        for chunk in messages.chunks(MAX_BATCH_SIZE) {
+            match self.send_chunk(chunk).await {
+                Ok(results) => outcomes.extend(results),
+                // 整个请求失败时，这一批中的每封邮件都以同样的原因失败
+                Err(e) => outcomes.extend(chunk.iter().map(|_| Err(e.duplicate()))),
+            }
+        }
+        outcomes
+    }
+
+    /// 只要收到 HTTP 响应即认为可达，不关心状态码
+    async fn ping(&self) -> Result<(), anyhow::Error> {
+        self.http_client.get(&self.base_url).send().await?;
+        Ok(())
+    }
+}
+
+impl PostmarkTransport {
+    /// 带上认证信息与当前的 trace 上下文，Postmark 侧的日志可以与我们的 trace 关联
+    fn post(&self, url: &str) -> RequestBuilder {
+        let mut request = self.http_client.post(url).header(
+            "X-Postmark-Server-Token",
+            self.authorization_token.expose_secret(),
+        );
+        for (name, value) in trace_context_headers() {
+            request = request.header(name, value);
+        }
+        request
+    }
+
+    /// 通过 `/email/batch` 一次发送最多 [`MAX_BATCH_SIZE`] 封邮件，按顺序返回每封邮件的结果
+    #[tracing::instrument(
+        name = "Send a batch of emails through Postmark",
+        skip_all,
+        fields(batch_size = chunk.len())
+    )]
+    async fn send_chunk(
+        &self,
+        chunk: &[EmailMessage],
+    ) -> Result<Vec<Result<SentEmail, EmailError>>, EmailError> {
+        let url = format!("{}/email/batch", self.base_url);
+        let request_body: Vec<_> = chunk.iter().map(SendEmailRequest::from).collect();
+        let response = self
+            .post(&url)
+            .json(&request_body)
+            .send()
+            .await
+            .map_err(|e| EmailError::Transient(e.into()))?;
+        // 与单封发送一样，请求已被接受时响应体无法解析也不能当作失败，否则会导致重复发送
+        let results: Vec<PostmarkResponse> = match check_status(response).await?.json().await {
+            Ok(results) => results,
+            Err(e) => {
+                tracing::warn!(
+                    error.message = %e,
+                    "Failed to parse the response to an accepted batch.",
+                );
+                return Ok(chunk.iter().map(|_| Ok(SentEmail::default())).collect());
+            }
+        };
+        // 无法确定哪些邮件已被接受，重试可能导致重复发送，交给人工在死信表中排查
+        if results.len() != chunk.len() {
+            return Err(EmailError::Permanent(anyhow::anyhow!(
+                "Postmark returned {} results for a batch of {} messages.",
+                results.len(),
+                chunk.len()
+            )));
+        }
+        Ok(results
+            .into_iter()
+            .map(PostmarkResponse::into_outcome)
+            .collect())
+    }
+}
+
+/// 非 2xx 响应时解析 Postmark 返回的 `ErrorCode` 与 `Message`
+async fn check_status(response: Response) -> Result<Response, EmailError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await.unwrap_or_default();
+    let error = serde_json::from_str::<PostmarkResponse>(&body)
+        .map(PostmarkResponse::into_provider_error)
+        .unwrap_or_else(|_| ProviderError {
+            error_code: 0,
+            message: if body.is_empty() {
+                status.to_string()
+            } else {
+                redact_emails(&body)
+            },
+        });
+    Err(classify(Some(status), error))
+}
+
+fn classify(status: Option<StatusCode>, error: ProviderError) -> EmailError {
+    match status {
+        Some(StatusCode::TOO_MANY_REQUESTS) => return EmailError::RateLimited(error),
+        Some(status) if is_retryable_status(status) => return EmailError::ServerError(error),
+        _ => {}
+    }
+    match error.error_code {
+        INACTIVE_RECIPIENT => EmailError::InactiveRecipient(error),
+        SENDER_SIGNATURE_NOT_FOUND | SENDER_SIGNATURE_NOT_CONFIRMED => {
+            EmailError::InvalidSender(error)
+        }
+        _ => EmailError::Rejected(error),
+    }
+}
+
+/// 5xx 与 429 稍后重试可能成功，其他 4xx 重试没有意义
+fn is_retryable_status(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+#[derive(serde::Serialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct SendEmailRequest<'a> {
+    from: &'a str,
+    to: &'a str,
+    subject: &'a str,
+    html_body: &'a str,
+    text_body: &'a str,
+    headers: Vec<EmailHeader<'a>>,
+}
+
+impl<'a> From<&'a EmailMessage> for SendEmailRequest<'a> {
+    fn from(message: &'a EmailMessage) -> Self {
+        Self {
+            from: &message.from,
+            to: &message.to,
+            subject: &message.subject,
+            html_body: &message.html_body,
+            text_body: &message.text_body,
+            headers: message
+                .headers
+                .iter()
+                .map(|(name, value)| EmailHeader { name, value })
+                .collect(),
+        }
+    }
+}
+
+#[derive(serde::Serialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct EmailHeader<'a> {
+    name: &'a str,
+    value: &'a str,
+}
+
+/// Postmark 的响应体：发送成功时 `ErrorCode` 为 0 并带有 `MessageID`，
+/// 失败时只有 `ErrorCode` 与 `Message`；批量发送的结果数组中每一项也是这个格式
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct PostmarkResponse {
+    #[serde(default)]
+    error_code: i64,
+    #[serde(default)]
+    message: String,
+    #[serde(rename = "MessageID")]
+    message_id: Option<String>,
+}
+
+impl PostmarkResponse {
+    /// 错误信息中可能包含收件人地址，先脱敏再随错误写入日志与死信表
+    fn into_provider_error(self) -> ProviderError {
+        ProviderError {
+            error_code: self.error_code,
+            message: redact_emails(&self.message),
+        }
+    }
+
+    fn into_outcome(self) -> Result<SentEmail, EmailError> {
+        if self.error_code == 0 {
+            Ok(SentEmail {
+                message_id: self.message_id,
+            })
+        } else {
+            Err(classify(None, self.into_provider_error()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::email_client::postmark::{is_retryable_status, PostmarkTransport, MAX_BATCH_SIZE};
+    use crate::email_client::{EmailError, EmailMessage, EmailTransport, SentEmail};
+    use claim::assert_ok;
+    use reqwest::StatusCode;
+    use secrecy::SecretBox;
+    use std::time::Duration;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+    fn transport(base_url: String) -> PostmarkTransport {
+        PostmarkTransport::new(
+            base_url,
+            SecretBox::new(Box::new("token".into())),
+            Duration::from_millis(200),
+        )
+        .unwrap()
+    }
+
+    fn messages(n: usize) -> Vec<EmailMessage> {
+        (0..n)
+            .map(|i| EmailMessage {
+                from: "sender@example.com".into(),
+                to: format!("recipient{}@example.com", i),
+                subject: "Hello".into(),
+                html_body: "<p>Hello</p>".into(),
+                text_body: "Hello".into(),
+                headers: vec![],
+            })
+            .collect()
+    }
+
+    /// 模拟 Postmark：地址以 `fail` 为前缀的收件人返回 406，其余成功
+    fn batch_response(request: &Request) -> ResponseTemplate {
+        let body: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+        let results: Vec<_> = body
+            .iter()
+            .map(|m| {
+                let to = m["To"].as_str().unwrap();
+                if to.starts_with("fail") {
+                    serde_json::json!({
+                        "ErrorCode": 406,
+                        "Message": "You tried to send to a recipient that has been marked as inactive.",
+                        "To": to,
+                    })
+                } else {
+                    serde_json::json!({ "ErrorCode": 0, "Message": "OK", "To": to })
+                }
+            })
+            .collect();
+        ResponseTemplate::new(200).set_body_json(results)
+    }
+
+    #[tokio::test]
+    async fn batches_report_the_outcome_of_every_message() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/email/batch"))
+            .respond_with(batch_response)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let mut messages = messages(3);
+        messages[1].to = "fail@example.com".into();
+
+        let outcomes = transport(mock_server.uri()).send_batch(&messages).await;
+
+        assert_eq!(outcomes.len(), 3);
+        assert_ok!(&outcomes[0]);
+        assert!(matches!(outcomes[1], Err(EmailError::InactiveRecipient(_))));
+        assert_ok!(&outcomes[2]);
+    }
+
+    #[tokio::test]
+    async fn large_batches_are_split_into_chunks() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/email/batch"))
+            .respond_with(batch_response)
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        let outcomes = transport(mock_server.uri())
+            .send_batch(&messages(MAX_BATCH_SIZE + 1))
+            .await;
+
+        assert_eq!(outcomes.len(), MAX_BATCH_SIZE + 1);
+        assert!(outcomes.iter().all(|o| o.is_ok()));
+    }
+
+    #[tokio::test]
+    async fn a_failed_batch_request_fails_every_message() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/email/batch"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcomes = transport(mock_server.uri()).send_batch(&messages(2)).await;
+
+        assert_eq!(outcomes.len(), 2);
+        assert!(outcomes
+            .iter()
+            .all(|o| matches!(o, Err(EmailError::ServerError(_)))));
+    }
+
+    #[tokio::test]
+    async fn an_accepted_batch_with_an_unreadable_body_is_not_retried() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/email/batch"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("not json"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcomes = transport(mock_server.uri()).send_batch(&messages(2)).await;
+
+        assert_eq!(outcomes.len(), 2);
+        assert!(outcomes
+            .iter()
+            .all(|o| matches!(o, Ok(sent) if *sent == SentEmail::default())));
+    }
+
+    #[tokio::test]
+    async fn a_batch_with_a_mismatched_number_of_results_is_not_retried() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/email/batch"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+                { "ErrorCode": 0, "Message": "OK" },
+            ])))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcomes = transport(mock_server.uri()).send_batch(&messages(2)).await;
+
+        assert_eq!(outcomes.len(), 2);
+        assert!(outcomes
+            .iter()
+            .all(|o| matches!(o, Err(e) if !e.is_retryable())));
+    }
+
+    /// 用给定的响应模拟 Postmark 的 `/email` 接口并发送一封邮件
+    async fn send_with_response(response: ResponseTemplate) -> Result<SentEmail, EmailError> {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/email"))
+            .respond_with(response)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        transport(mock_server.uri()).send(&messages(1)[0]).await
+    }
+
+    fn error_response(status: u16, error_code: i64, message: &str) -> ResponseTemplate {
+        ResponseTemplate::new(status).set_body_json(serde_json::json!({
+            "ErrorCode": error_code,
+            "Message": message,
+        }))
+    }
+
+    #[tokio::test]
+    async fn the_message_id_is_captured_on_success() {
+        let response = ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "To": "recipient0@example.com",
+            "SubmittedAt": "2014-02-17T07:25:01.4178645-05:00",
+            "MessageID": "0a129aee-e1cd-480d-b08d-4f48548ff48d",
+            "ErrorCode": 0,
+            "Message": "OK",
+        }));
+
+        let sent = send_with_response(response).await.unwrap();
+
+        assert_eq!(
+            sent.message_id.as_deref(),
+            Some("0a129aee-e1cd-480d-b08d-4f48548ff48d")
+        );
+    }
+
+    #[tokio::test]
+    async fn inactive_recipients_are_recognised() {
+        let outcome = send_with_response(error_response(
+            422,
+            406,
+            "You tried to send to a recipient that has been marked as inactive.",
+        ))
+        .await;
+
+        match outcome {
+            Err(EmailError::InactiveRecipient(e)) => {
+                assert_eq!(e.error_code, 406);
+                assert!(e.message.contains("inactive"));
+            }
+            other => panic!("Unexpected outcome: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn invalid_sender_signatures_are_recognised() {
+        for error_code in [400, 401] {
+            let outcome = send_with_response(error_response(
+                422,
+                error_code,
+                "The 'From' address you supplied is not a Sender Signature on your account.",
+            ))
+            .await;
+
+            assert!(
+                matches!(outcome, Err(EmailError::InvalidSender(_))),
+                "{:?}",
+                outcome
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn rate_limits_and_server_errors_are_retryable() {
+        let rate_limited =
+            send_with_response(error_response(429, 429, "Rate limit exceeded.")).await;
+        let server_error = send_with_response(ResponseTemplate::new(503)).await;
+
+        assert!(matches!(&rate_limited, Err(EmailError::RateLimited(_))));
+        assert!(matches!(&server_error, Err(EmailError::ServerError(_))));
+        assert!(rate_limited.unwrap_err().is_retryable());
+        assert!(server_error.unwrap_err().is_retryable());
+    }
+
+    #[tokio::test]
+    async fn other_provider_errors_are_permanent_rejections() {
+        let outcome = send_with_response(error_response(422, 300, "Invalid email request.")).await;
+
+        let e = outcome.unwrap_err();
+        assert!(matches!(e, EmailError::Rejected(_)));
+        assert!(!e.is_retryable());
+        assert_eq!(
+            e.to_string(),
+            "The email provider rejected the message: Invalid email request. (error code 300)"
+        );
+    }
+
+    #[test]
+    fn server_errors_and_rate_limits_are_retryable() {
+        for status in [
+            StatusCode::INTERNAL_SERVER_ERROR,
+            StatusCode::BAD_GATEWAY,
+            StatusCode::SERVICE_UNAVAILABLE,
+            StatusCode::TOO_MANY_REQUESTS,
+        ] {
+            assert!(is_retryable_status(status));
+        }
+    }
+
+    #[test]
+    fn client_errors_are_permanent() {
+        for status in [
+            StatusCode::BAD_REQUEST,
+            StatusCode::UNAUTHORIZED,
+            StatusCode::UNPROCESSABLE_ENTITY,
+        ] {
+            assert!(!is_retryable_status(status));
+        }
+    }
+}
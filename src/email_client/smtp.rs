@@ -0,0 +1,198 @@
+use crate::email_client::{EmailError, EmailMessage, EmailTransport, SentEmail};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::transport::smtp::client::{Tls, TlsParameters};
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+use secrecy::{ExposeSecret, SecretBox};
+use std::time::Duration;
+
+/// SMTP 连接的加密方式
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    /// 明文连接，仅用于本地的 SMTP 测试服务
+    None,
+    /// 先明文连接，再通过 `STARTTLS` 升级
+    Starttls,
+    /// 直接建立 TLS 连接（通常是 465 端口）
+    Tls,
+}
+
+/// 通过 SMTP 服务器发送邮件，供无法使用 Postmark 的私有化部署使用
+pub struct SmtpTransport {
+    mailer: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl SmtpTransport {
+    pub fn new(
+        host: &str,
+        port: u16,
+        tls: SmtpTls,
+        credentials: Option<(String, &SecretBox<String>)>,
+        timeout: Duration,
+    ) -> Result<Self, anyhow::Error> {
+        let tls = match tls {
+            SmtpTls::None => Tls::None,
+            SmtpTls::Starttls => Tls::Required(TlsParameters::new(host.into())?),
+            SmtpTls::Tls => Tls::Wrapper(TlsParameters::new(host.into())?),
+        };
+        let mut builder = AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host)
+            .port(port)
+            .tls(tls)
+            .timeout(Some(timeout));
+        if let Some((username, password)) = credentials {
+            builder =
+                builder.credentials(Credentials::new(username, password.expose_secret().clone()));
+        }
+        Ok(Self {
+            mailer: builder.build(),
+        })
+    }
+}
+
+impl EmailTransport for SmtpTransport {
+    async fn send(&self, message: &EmailMessage) -> Result<SentEmail, EmailError> {
+        let mime = message.to_mime().map_err(EmailError::Permanent)?;
+        self.mailer.send(mime).await.map_err(|e| {
+            // 5xx 回复码表示服务器拒收，其他情况（4xx、连接失败、超时）稍后重试可能成功
+            if e.is_permanent() {
+                EmailError::Permanent(e.into())
+            } else {
+                EmailError::Transient(e.into())
+            }
+        })?;
+        Ok(SentEmail::default())
+    }
+
+    async fn ping(&self) -> Result<(), anyhow::Error> {
+        if !self.mailer.test_connection().await? {
+            anyhow::bail!("The SMTP server did not accept the connection.");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::email_client::smtp::{SmtpTls, SmtpTransport};
+    use crate::email_client::{EmailError, EmailMessage, EmailTransport};
+    use claim::{assert_err, assert_ok};
+    use std::time::Duration;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+    use tokio::task::JoinHandle;
+
+    /// 只实现最小子集的 SMTP 接收端：对 `RCPT TO` 回复 `rcpt_reply`，返回收到的 `DATA` 内容
+    async fn smtp_sink(rcpt_reply: &'static str) -> (u16, JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            let mut data = String::new();
+            writer.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+            while let Some(line) = lines.next_line().await.unwrap() {
+                let command = line.to_uppercase();
+                let reply = if command.starts_with("EHLO") {
+                    "250 localhost"
+                } else if command.starts_with("RCPT") {
+                    rcpt_reply
+                } else if command.starts_with("DATA") {
+                    writer
+                        .write_all(b"354 End data with <CR><LF>.<CR><LF>\r\n")
+                        .await
+                        .unwrap();
+                    while let Some(line) = lines.next_line().await.unwrap() {
+                        if line == "." {
+                            break;
+                        }
+                        data.push_str(&line);
+                        data.push('\n');
+                    }
+                    "250 OK"
+                } else if command.starts_with("QUIT") {
+                    writer.write_all(b"221 Bye\r\n").await.unwrap();
+                    break;
+                } else {
+                    "250 OK"
+                };
+                writer
+                    .write_all(format!("{}\r\n", reply).as_bytes())
+                    .await
+                    .unwrap();
+            }
+            data
+        });
+        (port, handle)
+    }
+
+    fn transport(port: u16) -> SmtpTransport {
+        SmtpTransport::new(
+            "127.0.0.1",
+            port,
+            SmtpTls::None,
+            None,
+            Duration::from_secs(5),
+        )
+        .unwrap()
+    }
+
+    fn message() -> EmailMessage {
+        EmailMessage {
+            from: "sender@example.com".into(),
+            to: "recipient@example.com".into(),
+            subject: "Hello".into(),
+            html_body: "<p>Hello</p>".into(),
+            text_body: "Hello".into(),
+            headers: vec![(
+                "List-Unsubscribe".into(),
+                "<https://example.com/unsubscribe>".into(),
+            )],
+        }
+    }
+
+    #[tokio::test]
+    async fn messages_are_delivered_to_the_smtp_server() {
+        let (port, sink) = smtp_sink("250 OK").await;
+
+        let outcome = transport(port).send(&message()).await;
+
+        assert_ok!(outcome);
+        let data = sink.await.unwrap();
+        assert!(data.contains("Subject: Hello"), "{}", data);
+        assert!(data.contains("List-Unsubscribe: <https://example.com/unsubscribe>"));
+    }
+
+    #[tokio::test]
+    async fn permanent_rejections_are_not_retried() {
+        let (port, _sink) = smtp_sink("550 No such user").await;
+
+        let outcome = transport(port).send(&message()).await;
+
+        assert!(matches!(outcome, Err(EmailError::Permanent(_))));
+    }
+
+    #[tokio::test]
+    async fn temporary_rejections_are_retried() {
+        let (port, _sink) = smtp_sink("451 Try again later").await;
+
+        let outcome = transport(port).send(&message()).await;
+
+        assert!(matches!(outcome, Err(EmailError::Transient(_))));
+    }
+
+    #[tokio::test]
+    async fn an_unreachable_server_is_a_transient_failure() {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        let outcome = transport(port).send(&message()).await;
+
+        assert_err!(&outcome);
+        assert!(matches!(outcome, Err(EmailError::Transient(_))));
+    }
+}
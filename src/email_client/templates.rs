@@ -0,0 +1,239 @@
+use anyhow::Context;
+use minijinja::{escape_formatter, AutoEscape, Environment, UndefinedBehavior};
+use std::path::Path;
+
+/// HTML 模板共用的基础布局，纯文本模板对应 `layout.txt`
+const LAYOUTS: [&str; 2] = ["layout.html", "layout.txt"];
+
+/// 纯文本正文自动生成时的换行宽度
+const TEXT_WIDTH: usize = 78;
+
+/// 系统发送的各类邮件，每个模板对应模板目录中的 `<name>.html`，以及可选的 `<name>.txt`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EmailTemplate {
+    Confirmation,
+    Welcome,
+    NewsletterIssue,
+    UnsubscribeConfirmation,
+}
+
+impl EmailTemplate {
+    pub const ALL: [EmailTemplate; 4] = [
+        EmailTemplate::Confirmation,
+        EmailTemplate::Welcome,
+        EmailTemplate::NewsletterIssue,
+        EmailTemplate::UnsubscribeConfirmation,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EmailTemplate::Confirmation => "confirmation",
+            EmailTemplate::Welcome => "welcome",
+            EmailTemplate::NewsletterIssue => "newsletter_issue",
+            EmailTemplate::UnsubscribeConfirmation => "unsubscribe_confirmation",
+        }
+    }
+}
+
+/// 渲染后的邮件正文
+#[derive(Debug)]
+pub struct RenderedEmail {
+    pub html: String,
+    pub text: String,
+}
+
+/// 启动时从磁盘加载并校验过的邮件模板
+pub struct EmailTemplates {
+    env: Environment<'static>,
+}
+
+impl EmailTemplates {
+    /// 加载目录下所有的 `.html` 与 `.txt` 模板；任何模板缺失或语法错误都会导致加载失败
+    pub fn load(directory: impl AsRef<Path>) -> Result<Self, anyhow::Error> {
+        let directory = directory.as_ref();
+        let mut env = Environment::new();
+        env.set_formatter(|out, state, value| match value.as_str() {
+            // 默认的 HTML 转义会把 `/` 也转义掉，导致链接在邮件源码中难以辨认
+            Some(s) if state.auto_escape() == AutoEscape::Html && !value.is_safe() => {
+                out.write_str(&escape_html(s))?;
+                Ok(())
+            }
+            _ => escape_formatter(out, state, value),
+        });
+        let entries = std::fs::read_dir(directory)
+            .with_context(|| format!("Failed to read {}.", directory.display()))?;
+        for entry in entries {
+            let path = entry?.path();
+            let (Some(name), Some("html" | "txt")) = (
+                path.file_name().and_then(|n| n.to_str()),
+                path.extension().and_then(|e| e.to_str()),
+            ) else {
+                continue;
+            };
+            let source = std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read {}.", path.display()))?;
+            env.add_template_owned(name.to_owned(), source)
+                .with_context(|| format!("Failed to parse {}.", path.display()))?;
+        }
+        for name in LAYOUTS {
+            env.get_template(name)
+                .with_context(|| format!("Missing the {} layout.", name))?;
+        }
+        // 用空的上下文渲染一遍，提前发现引用了不存在的布局或子模板等问题
+        for template in EmailTemplate::ALL {
+            let html = format!("{}.html", template.name());
+            env.get_template(&html)
+                .and_then(|t| t.render(()))
+                .with_context(|| format!("Invalid template {}.", html))?;
+            let text = format!("{}.txt", template.name());
+            if let Ok(t) = env.get_template(&text) {
+                t.render(())
+                    .with_context(|| format!("Invalid template {}.", text))?;
+            }
+        }
+        // 校验完成后才启用严格模式，渲染时缺少变量会直接报错
+        env.set_undefined_behavior(UndefinedBehavior::Strict);
+        Ok(Self { env })
+    }
+
+    /// 渲染 HTML 正文；没有对应的 `.txt` 模板时，由 HTML 自动生成纯文本正文
+    pub fn render(
+        &self,
+        template: EmailTemplate,
+        context: impl serde::Serialize,
+    ) -> Result<RenderedEmail, anyhow::Error> {
+        let context = minijinja::Value::from_serialize(context);
+        let html = self
+            .env
+            .get_template(&format!("{}.html", template.name()))?
+            .render(&context)
+            .with_context(|| format!("Failed to render the {} template.", template.name()))?;
+        let text = match self.env.get_template(&format!("{}.txt", template.name())) {
+            Ok(t) => t
+                .render(&context)
+                .with_context(|| format!("Failed to render the {} template.", template.name()))?,
+            Err(_) => html2text::from_read(html.as_bytes(), TEXT_WIDTH),
+        };
+        Ok(RenderedEmail { html, text })
+    }
+}
+
+fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::email_client::templates::{EmailTemplate, EmailTemplates};
+    use claim::{assert_err, assert_ok};
+    use minijinja::context;
+
+    const TEMPLATES: &str = "templates/email";
+
+    fn templates() -> EmailTemplates {
+        EmailTemplates::load(TEMPLATES).unwrap()
+    }
+
+    #[test]
+    fn every_bundled_template_parses() {
+        assert_ok!(EmailTemplates::load(TEMPLATES));
+    }
+
+    #[test]
+    fn variables_are_escaped_in_html_but_not_in_text() {
+        let rendered = templates()
+            .render(
+                EmailTemplate::Confirmation,
+                context! { confirmation_link => "https://example.com/confirm?a=1&b=<2>" },
+            )
+            .unwrap();
+
+        assert!(rendered
+            .html
+            .contains("https://example.com/confirm?a=1&amp;b=&lt;2&gt;"));
+        assert!(rendered
+            .text
+            .contains("https://example.com/confirm?a=1&b=<2>"));
+    }
+
+    #[test]
+    fn templates_use_the_base_layout() {
+        let rendered = templates()
+            .render(
+                EmailTemplate::Welcome,
+                context! { unsubscribe_url => "https://example.com/unsubscribe" },
+            )
+            .unwrap();
+
+        assert!(rendered.html.starts_with("<!DOCTYPE html>"));
+        assert!(rendered.html.contains("</html>"));
+    }
+
+    #[test]
+    fn the_text_part_is_generated_from_html_when_missing() {
+        let rendered = templates()
+            .render(
+                EmailTemplate::Welcome,
+                context! { unsubscribe_url => "https://example.com/unsubscribe" },
+            )
+            .unwrap();
+
+        assert!(!rendered.text.contains('<'), "{}", rendered.text);
+        assert!(rendered.text.contains("https://example.com/unsubscribe"));
+    }
+
+    #[test]
+    fn trusted_html_can_be_inserted_unescaped() {
+        let rendered = templates()
+            .render(
+                EmailTemplate::NewsletterIssue,
+                context! {
+                    title => "Issue #1",
+                    html_content => "<p>Newsletter body</p>",
+                    text_content => "Newsletter body",
+                    unsubscribe_url => "https://example.com/unsubscribe",
+                    tracking_pixel_url => (),
+                    tracking_opt_out_url => (),
+                },
+            )
+            .unwrap();
+
+        assert!(rendered.html.contains("<p>Newsletter body</p>"));
+        assert!(rendered.text.contains("Newsletter body"));
+    }
+
+    #[test]
+    fn missing_variables_are_an_error() {
+        assert_err!(templates().render(EmailTemplate::Confirmation, context! {}));
+    }
+
+    #[test]
+    fn syntax_errors_are_reported_when_loading() {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&directory).unwrap();
+        for entry in std::fs::read_dir(TEMPLATES).unwrap() {
+            let path = entry.unwrap().path();
+            std::fs::copy(&path, directory.join(path.file_name().unwrap())).unwrap();
+        }
+        std::fs::write(directory.join("welcome.html"), "{% if %}").unwrap();
+
+        let error = EmailTemplates::load(&directory).err().unwrap();
+
+        assert!(
+            format!("{:#}", error).contains("welcome.html"),
+            "{:#}",
+            error
+        );
+    }
+}
@@ -0,0 +1,54 @@
+mod key;
+mod persistence;
+
+pub use key::IdempotencyKey;
+pub use persistence::{get_saved_response, save_response, try_processing, NextAction};
+
+use actix_web::{HttpRequest, HttpResponse};
+use sqlx::PgPool;
+use std::future::Future;
+
+/// 客户端在重试时携带的请求头
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+/// 从请求头中读取幂等键，缺省时返回 `None`
+pub fn idempotency_key_from_request(
+    request: &HttpRequest,
+) -> Result<Option<IdempotencyKey>, anyhow::Error> {
+    let header_value = match request.headers().get(IDEMPOTENCY_KEY_HEADER) {
+        Some(header_value) => header_value,
+        None => return Ok(None),
+    };
+    let key = header_value
+        .to_str()
+        .map_err(|_| anyhow::anyhow!("The idempotency key must be a valid UTF8 string."))?
+        .to_owned();
+    Ok(Some(key.try_into()?))
+}
+
+/// 在幂等键的保护下执行 `handler`：没有幂等键时直接执行；
+/// 首次出现的键执行后保存响应；重复的键直接回放已保存的响应。
+/// `handler` 返回错误时不保存任何内容，客户端重试时会重新执行。
+pub async fn execute_idempotently<F, Fut, E>(
+    pool: &PgPool,
+    idempotency_key: Option<IdempotencyKey>,
+    scope: &str,
+    handler: F,
+) -> Result<HttpResponse, E>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<HttpResponse, E>>,
+    E: From<anyhow::Error>,
+{
+    let idempotency_key = match idempotency_key {
+        Some(idempotency_key) => idempotency_key,
+        None => return handler().await,
+    };
+    match try_processing(pool, &idempotency_key, scope).await? {
+        NextAction::StartProcessing(transaction) => {
+            let response = handler().await?;
+            Ok(save_response(*transaction, &idempotency_key, scope, response).await?)
+        }
+        NextAction::ReturnSavedResponse(saved_response) => Ok(saved_response),
+    }
+}
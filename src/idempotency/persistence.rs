@@ -0,0 +1,141 @@
+use super::IdempotencyKey;
+use crate::metrics::begin_transaction;
+use actix_web::body::to_bytes;
+use actix_web::http::StatusCode;
+use actix_web::HttpResponse;
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+
+#[derive(Debug, sqlx::Type)]
+#[sqlx(type_name = "header_pair")]
+struct HeaderPairRecord {
+    name: String,
+    value: Vec<u8>,
+}
+
+pub enum NextAction {
+    /// 首次出现的幂等键，持有的事务会锁住该键直到响应被保存
+    StartProcessing(Box<Transaction<'static, Postgres>>),
+    ReturnSavedResponse(HttpResponse),
+}
+
+#[tracing::instrument(name = "Get saved idempotent response", skip(pool, idempotency_key))]
+pub async fn get_saved_response(
+    pool: &PgPool,
+    idempotency_key: &IdempotencyKey,
+    scope: &str,
+) -> Result<Option<HttpResponse>, anyhow::Error> {
+    let saved_response = sqlx::query!(
+        r#"
+        SELECT
+            response_status_code as "response_status_code!",
+            response_headers as "response_headers!: Vec<HeaderPairRecord>",
+            response_body as "response_body!"
+        FROM idempotency
+        WHERE
+            scope = $1 AND
+            idempotency_key = $2
+        "#,
+        scope,
+        idempotency_key.as_ref(),
+    )
+    .fetch_optional(pool)
+    .await?;
+    if let Some(r) = saved_response {
+        let status_code = StatusCode::from_u16(r.response_status_code.try_into()?)?;
+        let mut response = HttpResponse::build(status_code);
+        for HeaderPairRecord { name, value } in r.response_headers {
+            response.append_header((name, value));
+        }
+        Ok(Some(response.body(r.response_body)))
+    } else {
+        Ok(None)
+    }
+}
+
+/// 抢占幂等键：插入成功则开始处理，否则返回已保存的响应。
+/// 并发请求会阻塞在插入语句上，直到先到的请求提交事务。
+#[tracing::instrument(
+    name = "Try processing idempotent request",
+    skip(pool, idempotency_key)
+)]
+pub async fn try_processing(
+    pool: &PgPool,
+    idempotency_key: &IdempotencyKey,
+    scope: &str,
+) -> Result<NextAction, anyhow::Error> {
+    let mut transaction = begin_transaction(pool).await?;
+    let n_inserted_rows = sqlx::query!(
+        r#"
+        INSERT INTO idempotency (scope, idempotency_key, created_at)
+        VALUES ($1, $2, $3)
+        ON CONFLICT DO NOTHING
+        "#,
+        scope,
+        idempotency_key.as_ref(),
+        Utc::now(),
+    )
+    .execute(&mut *transaction)
+    .await?
+    .rows_affected();
+    if n_inserted_rows > 0 {
+        Ok(NextAction::StartProcessing(Box::new(transaction)))
+    } else {
+        let saved_response = get_saved_response(pool, idempotency_key, scope)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("We expected a saved response, we didn't find it"))?;
+        Ok(NextAction::ReturnSavedResponse(saved_response))
+    }
+}
+
+/// 保存响应并提交事务。服务端错误不会被保存，客户端重试时会重新执行。
+#[tracing::instrument(
+    name = "Save idempotent response",
+    skip(transaction, idempotency_key, http_response)
+)]
+pub async fn save_response(
+    mut transaction: Transaction<'static, Postgres>,
+    idempotency_key: &IdempotencyKey,
+    scope: &str,
+    http_response: HttpResponse,
+) -> Result<HttpResponse, anyhow::Error> {
+    if http_response.status().is_server_error() {
+        transaction.rollback().await?;
+        return Ok(http_response);
+    }
+    let (response_head, body) = http_response.into_parts();
+    let body = to_bytes(body).await.map_err(|e| anyhow::anyhow!("{}", e))?;
+    let status_code = response_head.status().as_u16() as i16;
+    let headers = {
+        let mut h = Vec::with_capacity(response_head.headers().len());
+        for (name, value) in response_head.headers().iter() {
+            let name = name.as_str().to_owned();
+            let value = value.as_bytes().to_owned();
+            h.push(HeaderPairRecord { name, value });
+        }
+        h
+    };
+    sqlx::query_unchecked!(
+        r#"
+        UPDATE idempotency
+        SET
+            response_status_code = $3,
+            response_headers = $4,
+            response_body = $5
+        WHERE
+            scope = $1 AND
+            idempotency_key = $2
+        "#,
+        scope,
+        idempotency_key.as_ref(),
+        status_code,
+        headers,
+        body.as_ref(),
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+
+    let http_response = response_head.set_body(body).map_into_boxed_body();
+    Ok(http_response)
+}
@@ -0,0 +1,544 @@
+use crate::configuration::Settings;
+use crate::domain::subscriber_email::SubscriberEmail;
+use crate::domain::tracking_token::TrackingLinks;
+use crate::domain::unsubscribe_token::UnsubscribeLinks;
+use crate::email_client::{
+    EmailClient, EmailError, EmailTemplate, SentEmail, TemplateEmail, MAX_BATCH_SIZE,
+};
+use crate::metrics::begin_transaction;
+use crate::startup::get_connection_pool;
+use crate::telemetry::redact;
+use chrono::Utc;
+use minijinja::context;
+use rand::Rng;
+use sqlx::PgPool;
+use std::collections::hash_map::{Entry, HashMap};
+use std::time::Duration;
+use tracing::{field::display, Instrument, Span};
+use uuid::Uuid;
+
+/// 取出的任务在这段时间内不会被其他 worker 再次取出；worker 在发送后崩溃或记录结果失败时，
+/// 任务在租约到期后重新投递
+const LEASE_DURATION: Duration = Duration::from_secs(5 * 60);
+
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+}
+
+/// 投递失败后的重试策略
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    pub max_retries: u16,
+    pub base_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// 第 `n_retries` 次重试前的等待时间：指数增长并封顶，再在后半段加入随机抖动，
+    /// 避免大量失败任务在同一时刻一起重试
+    pub fn backoff(&self, n_retries: u16) -> Duration {
+        let exponential = self
+            .base_backoff
+            .saturating_mul(2u32.saturating_pow(n_retries.into()));
+        let capped = exponential.min(self.max_backoff);
+        let half = capped / 2;
+        half + rand::thread_rng().gen_range(Duration::ZERO..=half)
+    }
+}
+
+/// 投递失败的类型
+#[derive(Debug, PartialEq, Eq)]
+pub enum FailureKind {
+    /// 超时、连接失败、服务端错误以及限流，稍后重试可能成功
+    Retryable,
+    /// 邮件被拒收以及无效的收件人，重试没有意义
+    Permanent,
+}
+
+impl FailureKind {
+    pub fn from_error(e: &EmailError) -> Self {
+        if e.is_retryable() {
+            FailureKind::Retryable
+        } else {
+            FailureKind::Permanent
+        }
+    }
+}
+
+pub async fn run_worker_until_stopped(configuration: Settings) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    let email_client = configuration.email_client.client()?;
+    let retry_policy = configuration.delivery_worker.retry_policy();
+    let unsubscribe_links = configuration.application.unsubscribe_links();
+    let tracking_links = configuration
+        .tracking
+        .enabled
+        .then(|| configuration.application.tracking_links());
+    worker_loop(
+        connection_pool,
+        email_client,
+        retry_policy,
+        unsubscribe_links,
+        tracking_links,
+    )
+    .await
+}
+
+async fn worker_loop(
+    pool: PgPool,
+    email_client: EmailClient,
+    retry_policy: RetryPolicy,
+    unsubscribe_links: UnsubscribeLinks,
+    tracking_links: Option<TrackingLinks>,
+) -> Result<(), anyhow::Error> {
+    loop {
+        match try_execute_task(
+            &pool,
+            &email_client,
+            &retry_policy,
+            &unsubscribe_links,
+            tracking_links.as_ref(),
+        )
+        .await
+        {
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+            }
+            Err(_) => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+}
+
+/// 从队列中租出一批到期的投递任务，通过一次批量请求发送：每个任务按各自的结果处理，
+/// 成功后删除，可重试的失败按退避策略重新排期，永久失败或重试耗尽则转入死信表。
+/// 每个任务的结果单独写入，一个任务写入失败不会让整批邮件重新发送；
+/// 传入 `tracking_links` 时，为未拒绝追踪的订阅者加入打开像素并改写正文中的链接
+#[tracing::instrument(skip_all, fields(batch_size = tracing::field::Empty), err)]
+pub async fn try_execute_task(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    retry_policy: &RetryPolicy,
+    unsubscribe_links: &UnsubscribeLinks,
+    tracking_links: Option<&TrackingLinks>,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let tasks = lease_tasks(pool, MAX_BATCH_SIZE).await?;
+    if tasks.is_empty() {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    }
+    Span::current().record("batch_size", tasks.len());
+    let mut issues = HashMap::new();
+    let mut pending = Vec::with_capacity(tasks.len());
+    let mut emails = Vec::with_capacity(tasks.len());
+    for task in &tasks {
+        let span = task.span();
+        let email = prepare_task(pool, &mut issues, task, unsubscribe_links, tracking_links)
+            .instrument(span.clone())
+            .await?;
+        if let Some(email) = email {
+            emails.push(email);
+            pending.push((task, span));
+        }
+    }
+    let outcomes = email_client
+        .send_template_batch(EmailTemplate::NewsletterIssue, &emails)
+        .await;
+    for ((task, span), outcome) in pending.into_iter().zip(outcomes) {
+        if let Err(e) = complete_task(pool, task, retry_policy, outcome)
+            .instrument(span.clone())
+            .await
+        {
+            // 邮件已经发出，任务留在队列中等租约到期；只有这一封可能被重复发送
+            span.in_scope(|| {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to record the outcome of a delivery.",
+                )
+            });
+        }
+    }
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+/// 检查任务是否还需要投递：订阅者已退订时删除任务，联系方式无效时转入死信表，
+/// 否则返回要发送的邮件
+async fn prepare_task(
+    pool: &PgPool,
+    issues: &mut HashMap<Uuid, NewsletterIssue>,
+    task: &DeliveryTask,
+    unsubscribe_links: &UnsubscribeLinks,
+    tracking_links: Option<&TrackingLinks>,
+) -> Result<Option<TemplateEmail<minijinja::Value>>, anyhow::Error> {
+    // 入队之后才退订的订阅者不再投递
+    if !task.is_confirmed {
+        tracing::info!("Skipping a delivery. The subscriber is no longer confirmed.");
+        delete_task(pool, task).await?;
+        return Ok(None);
+    }
+    let email = match SubscriberEmail::parse(task.email.clone()) {
+        Ok(email) => email,
+        Err(e) => {
+            tracing::error!(
+                error.message = %e,
+                "Dead-lettering a delivery. The subscriber's stored contact details are invalid",
+            );
+            dead_letter_task(pool, task, &e.to_string()).await?;
+            return Ok(None);
+        }
+    };
+    // 同一批中的任务大多属于同一期简报，每期只查询一次
+    let issue = match issues.entry(task.issue_id) {
+        Entry::Occupied(entry) => entry.into_mut(),
+        Entry::Vacant(entry) => entry.insert(get_issue(pool, task.issue_id).await?),
+    };
+    Ok(Some(newsletter_email(
+        email,
+        issue,
+        task,
+        unsubscribe_links,
+        tracking_links,
+    )))
+}
+
+/// 为一个订阅者准备这一期简报的邮件
+fn newsletter_email(
+    email: SubscriberEmail,
+    issue: &NewsletterIssue,
+    task: &DeliveryTask,
+    unsubscribe_links: &UnsubscribeLinks,
+    tracking_links: Option<&TrackingLinks>,
+) -> TemplateEmail<minijinja::Value> {
+    let unsubscribe_url = unsubscribe_links.for_subscriber(task.subscriber_id);
+    let (html_content, tracking_pixel_url, tracking_opt_out_url) =
+        match tracking_links.filter(|_| !task.tracking_opt_out) {
+            Some(links) => (
+                links.track_links(&issue.html_content, task.issue_id, task.subscriber_id),
+                Some(links.open_pixel(task.issue_id, task.subscriber_id)),
+                Some(unsubscribe_links.tracking_opt_out_for_subscriber(task.subscriber_id)),
+            ),
+            None => (issue.html_content.clone(), None, None),
+        };
+    TemplateEmail {
+        recipient: email,
+        subject: issue.title.clone(),
+        context: context! {
+            title => &issue.title,
+            html_content => &html_content,
+            text_content => &issue.text_content,
+            unsubscribe_url => &unsubscribe_url,
+            tracking_pixel_url => &tracking_pixel_url,
+            tracking_opt_out_url => &tracking_opt_out_url,
+        },
+        unsubscribe_url,
+    }
+}
+
+/// 按一个任务自己的发送结果删除、重新排期或转入死信表
+async fn complete_task(
+    pool: &PgPool,
+    task: &DeliveryTask,
+    retry_policy: &RetryPolicy,
+    outcome: Result<SentEmail, EmailError>,
+) -> Result<(), anyhow::Error> {
+    match outcome {
+        Ok(sent) => {
+            if let Some(message_id) = &sent.message_id {
+                Span::current().record("message_id", display(message_id));
+            }
+            delete_task(pool, task).await?
+        }
+        Err(e) => {
+            let n_retries = task.n_retries.saturating_add(1);
+            let kind = FailureKind::from_error(&e);
+            if kind == FailureKind::Retryable && n_retries <= retry_policy.max_retries as i16 {
+                let delay = retry_policy.backoff(task.n_retries as u16);
+                tracing::warn!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    retry_in_ms = delay.as_millis() as u64,
+                    "Failed to deliver issue to a confirmed subscriber. Scheduling a retry.",
+                );
+                schedule_retry(pool, task, n_retries, delay, &e.to_string()).await?;
+            } else {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    failure_kind = ?kind,
+                    "Failed to deliver issue to a confirmed subscriber. Dead-lettering.",
+                );
+                dead_letter_task(pool, task, &e.to_string()).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+struct DeliveryTask {
+    issue_id: Uuid,
+    email: String,
+    n_retries: i16,
+    subscriber_id: Uuid,
+    is_confirmed: bool,
+    tracking_opt_out: bool,
+}
+
+impl DeliveryTask {
+    /// 处理该任务时的日志都记录在这个 span 中
+    fn span(&self) -> Span {
+        tracing::info_span!(
+            "Deliver an issue",
+            newsletter_issue_id = %self.issue_id,
+            subscriber_email = %redact(&self.email),
+            n_retries = self.n_retries,
+            message_id = tracing::field::Empty,
+        )
+    }
+}
+
+/// 租出最多 `limit` 个到期的任务：`SKIP LOCKED` 保证多个 worker 不会拿到同一个任务，
+/// 推迟 `execute_after` 到租约结束后，发送期间无需一直持有行锁
+#[tracing::instrument(skip_all)]
+async fn lease_tasks(pool: &PgPool, limit: usize) -> Result<Vec<DeliveryTask>, anyhow::Error> {
+    let rows = sqlx::query!(
+        r#"
+        WITH leased AS (
+            SELECT q.newsletter_issue_id, q.subscriber_email
+            FROM issue_delivery_queue q
+            JOIN subscriptions s ON s.email = q.subscriber_email
+            WHERE q.execute_after <= now()
+            FOR UPDATE OF q
+            SKIP LOCKED
+            LIMIT $1
+        )
+        UPDATE issue_delivery_queue q
+        SET execute_after = now() + make_interval(secs => $2)
+        FROM leased
+        JOIN subscriptions s ON s.email = leased.subscriber_email
+        WHERE
+            q.newsletter_issue_id = leased.newsletter_issue_id AND
+            q.subscriber_email = leased.subscriber_email
+        RETURNING
+            q.newsletter_issue_id,
+            q.subscriber_email,
+            q.n_retries,
+            s.id AS subscriber_id,
+            s.status = 'confirmed' AS "is_confirmed!",
+            s.tracking_opt_out
+        "#,
+        limit as i64,
+        LEASE_DURATION.as_secs_f64(),
+    )
+    .fetch_all(pool)
+    .await?;
+    let tasks = rows
+        .into_iter()
+        .map(|r| DeliveryTask {
+            issue_id: r.newsletter_issue_id,
+            email: r.subscriber_email,
+            n_retries: r.n_retries,
+            subscriber_id: r.subscriber_id,
+            is_confirmed: r.is_confirmed,
+            tracking_opt_out: r.tracking_opt_out,
+        })
+        .collect();
+    Ok(tasks)
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_task(pool: &PgPool, task: &DeliveryTask) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        task.issue_id,
+        task.email,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn schedule_retry(
+    pool: &PgPool,
+    task: &DeliveryTask,
+    n_retries: i16,
+    delay: Duration,
+    last_error: &str,
+) -> Result<(), anyhow::Error> {
+    let execute_after = Utc::now() + chrono::Duration::from_std(delay)?;
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET
+            n_retries = $3,
+            execute_after = $4,
+            last_error = $5
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        task.issue_id,
+        task.email,
+        n_retries,
+        execute_after,
+        last_error,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// 将任务从队列移入死信表，等待运维人员排查后重新入队
+#[tracing::instrument(skip_all)]
+async fn dead_letter_task(
+    pool: &PgPool,
+    task: &DeliveryTask,
+    last_error: &str,
+) -> Result<(), anyhow::Error> {
+    let mut transaction = begin_transaction(pool).await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_dead_letters (
+            newsletter_issue_id,
+            subscriber_email,
+            n_retries,
+            last_error,
+            failed_at
+        )
+        VALUES ($1, $2, $3, $4, now())
+        ON CONFLICT (newsletter_issue_id, subscriber_email) DO UPDATE
+        SET
+            n_retries = EXCLUDED.n_retries,
+            last_error = EXCLUDED.last_error,
+            failed_at = EXCLUDED.failed_at
+        "#,
+        task.issue_id,
+        task.email,
+        task.n_retries,
+        last_error,
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        task.issue_id,
+        task.email,
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+struct NewsletterIssue {
+    title: String,
+    text_content: String,
+    html_content: String,
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_issue(pool: &PgPool, issue_id: Uuid) -> Result<NewsletterIssue, anyhow::Error> {
+    let issue = sqlx::query_as!(
+        NewsletterIssue,
+        r#"
+        SELECT title, text_content, html_content
+        FROM newsletter_issues
+        WHERE
+            newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(issue)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::email_client::{EmailError, ProviderError};
+    use crate::issue_delivery_worker::{FailureKind, RetryPolicy};
+    use std::time::Duration;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 5,
+            base_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+        }
+    }
+
+    #[test]
+    fn backoff_grows_exponentially_within_jitter_bounds() {
+        for n_retries in 0..5 {
+            let expected = Duration::from_secs(2u64.pow(n_retries.into()));
+            let backoff = policy().backoff(n_retries);
+            assert!(
+                backoff >= expected / 2,
+                "{:?} < {:?}",
+                backoff,
+                expected / 2
+            );
+            assert!(backoff <= expected, "{:?} > {:?}", backoff, expected);
+        }
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        let backoff = policy().backoff(u16::MAX);
+        assert!(backoff <= Duration::from_secs(60));
+        assert!(backoff >= Duration::from_secs(30));
+    }
+
+    #[test]
+    fn transient_email_errors_are_retryable() {
+        let e = EmailError::Transient(anyhow::anyhow!("Connection refused"));
+        assert_eq!(FailureKind::from_error(&e), FailureKind::Retryable);
+    }
+
+    #[test]
+    fn permanent_email_errors_are_not_retried() {
+        let e = EmailError::Permanent(anyhow::anyhow!("Inactive recipient"));
+        assert_eq!(FailureKind::from_error(&e), FailureKind::Permanent);
+    }
+
+    #[test]
+    fn provider_errors_are_retried_only_when_the_provider_is_at_fault() {
+        let error = || ProviderError {
+            error_code: 0,
+            message: "Something happened".into(),
+        };
+        assert_eq!(
+            FailureKind::from_error(&EmailError::RateLimited(error())),
+            FailureKind::Retryable
+        );
+        assert_eq!(
+            FailureKind::from_error(&EmailError::ServerError(error())),
+            FailureKind::Retryable
+        );
+        assert_eq!(
+            FailureKind::from_error(&EmailError::InactiveRecipient(error())),
+            FailureKind::Permanent
+        );
+        assert_eq!(
+            FailureKind::from_error(&EmailError::InvalidSender(error())),
+            FailureKind::Permanent
+        );
+        assert_eq!(
+            FailureKind::from_error(&EmailError::Rejected(error())),
+            FailureKind::Permanent
+        );
+    }
+}
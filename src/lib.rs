@@ -1,6 +1,14 @@
+pub mod authentication;
 pub mod configuration;
 pub mod domain;
 pub mod email_client;
+pub mod idempotency;
+pub mod issue_delivery_worker;
+pub mod metrics;
+pub mod request_id;
 pub mod routes;
+pub mod session_state;
+pub mod session_store;
 pub mod startup;
 pub mod telemetry;
+pub mod utils;
@@ -1,15 +1,89 @@
 use actix_demo::{
     configuration::get_configuration,
+    issue_delivery_worker::run_worker_until_stopped,
     startup::Application,
-    telemetry::{get_subscriber, init_subscriber},
+    telemetry::{get_subscriber, get_tracer_provider, init_subscriber, set_redaction},
 };
+use std::fmt::{Debug, Display};
+use tokio::task::JoinError;
 
 #[actix_web::main]
-async fn main() -> std::io::Result<()> {
-    let subscriber = get_subscriber("actix-demo".into(), "info".into(), std::io::stdout);
+async fn main() -> anyhow::Result<()> {
+    // 配置无法读取或有误时列出问题后直接退出，而不是 panic 或在运行中途失败
+    let configuration = match get_configuration() {
+        Ok(configuration) => configuration,
+        Err(e) => {
+            eprintln!("Failed to read configuration: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Err(e) = configuration.validate() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    let tracer_provider = get_tracer_provider("actix-demo", &configuration.telemetry)?;
+    let log = &configuration.log;
+    set_redaction(log.redaction, &configuration.application.hmac_secret);
+    let (subscriber, log_filter) = get_subscriber(
+        "actix-demo".into(),
+        log.filter.clone(),
+        log.format,
+        log.file.is_none(),
+        log.writer()?,
+        &tracer_provider,
+    );
     init_subscriber(subscriber);
-    let configuration = get_configuration().expect("Failed to read configuration.");
-    let app = Application::build(configuration).await?;
-    app.run_until_stopped().await?;
-    Ok(())
+    // 读取配置时日志还未初始化，在这里补记配置的来源；密钥类字段均为 `SecretBox`，`Debug` 输出时已脱敏
+    tracing::info!(
+        environment = configuration.environment.as_str(),
+        sources = ?configuration.sources,
+        configuration = ?configuration,
+        "Loaded configuration"
+    );
+    let application = Application::build(&configuration, log_filter).await?;
+    let application_task = tokio::spawn(application.run_until_stopped());
+    let worker_task = tokio::spawn(run_worker_until_stopped(configuration));
+
+    // 任意一个任务退出时，整个进程随之退出；任务失败时以非零状态码退出，便于编排系统重启进程
+    let outcome = tokio::select! {
+        o = application_task => report_exit("API", o),
+        o = worker_task => report_exit("Background worker", o),
+    };
+    // 退出前导出还在缓冲区中的 span
+    tracer_provider.shutdown()?;
+    outcome
+}
+
+fn report_exit(
+    task_name: &str,
+    outcome: Result<Result<(), impl Debug + Display>, JoinError>,
+) -> anyhow::Result<()> {
+    match outcome {
+        Ok(Ok(())) => {
+            tracing::info!("{} has exited", task_name);
+            Ok(())
+        }
+        Ok(Err(e)) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "{} failed",
+                task_name
+            );
+            Err(anyhow::anyhow!("{} failed: {}", task_name, e))
+        }
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "{} task failed to complete",
+                task_name
+            );
+            Err(anyhow::anyhow!(
+                "{} task failed to complete: {}",
+                task_name,
+                e
+            ))
+        }
+    }
 }
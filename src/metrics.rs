@@ -0,0 +1,233 @@
+use crate::email_client::{EmailError, SentEmail};
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{web, HttpResponse};
+use once_cell::sync::Lazy;
+use prometheus::{
+    histogram_opts, opts, Encoder, Histogram, HistogramVec, IntCounterVec, IntGauge, Registry,
+    TextEncoder,
+};
+use sqlx::{PgPool, Postgres, Transaction};
+use std::time::{Duration, Instant};
+
+/// 进程内所有指标，API 与后台 worker 共用
+static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+/// 没有匹配到任何路由的请求统一记为该值，避免按原始路径产生无限多的时间序列
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+struct Metrics {
+    registry: Registry,
+    http_requests_total: IntCounterVec,
+    http_request_duration_seconds: HistogramVec,
+    db_pool_connections: IntGauge,
+    db_pool_idle_connections: IntGauge,
+    db_pool_max_connections: IntGauge,
+    db_pool_acquire_duration_seconds: Histogram,
+    email_sends_total: IntCounterVec,
+    email_send_duration_seconds: HistogramVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new();
+        let http_requests_total = IntCounterVec::new(
+            opts!("http_requests_total", "Number of HTTP requests handled."),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let http_request_duration_seconds = HistogramVec::new(
+            histogram_opts!(
+                "http_request_duration_seconds",
+                "Time spent handling HTTP requests."
+            ),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let db_pool_connections = IntGauge::new(
+            "db_pool_connections",
+            "Number of open connections in the database pool.",
+        )
+        .unwrap();
+        let db_pool_idle_connections = IntGauge::new(
+            "db_pool_idle_connections",
+            "Number of idle connections in the database pool.",
+        )
+        .unwrap();
+        let db_pool_max_connections = IntGauge::new(
+            "db_pool_max_connections",
+            "Maximum number of connections in the database pool.",
+        )
+        .unwrap();
+        let db_pool_acquire_duration_seconds = Histogram::with_opts(histogram_opts!(
+            "db_pool_acquire_duration_seconds",
+            "Time spent waiting for a connection from the database pool."
+        ))
+        .unwrap();
+        let email_sends_total = IntCounterVec::new(
+            opts!("email_sends_total", "Number of emails sent, by outcome."),
+            &["operation", "outcome"],
+        )
+        .unwrap();
+        let email_send_duration_seconds = HistogramVec::new(
+            histogram_opts!(
+                "email_send_duration_seconds",
+                "Time spent calling the email transport, by outcome."
+            ),
+            &["operation", "outcome"],
+        )
+        .unwrap();
+        registry
+            .register(Box::new(http_requests_total.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(http_request_duration_seconds.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(db_pool_connections.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(db_pool_idle_connections.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(db_pool_max_connections.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(db_pool_acquire_duration_seconds.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(email_sends_total.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(email_send_duration_seconds.clone()))
+            .unwrap();
+        Self {
+            registry,
+            http_requests_total,
+            http_request_duration_seconds,
+            db_pool_connections,
+            db_pool_idle_connections,
+            db_pool_max_connections,
+            db_pool_acquire_duration_seconds,
+            email_sends_total,
+            email_send_duration_seconds,
+        }
+    }
+}
+
+/// 按路由模板（例如 `/t/{token}`）与状态码统计请求数量与耗时
+pub async fn record_http_metrics(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let method = req.method().to_string();
+    let route = req
+        .match_pattern()
+        .unwrap_or_else(|| UNMATCHED_ROUTE.to_owned());
+    let start = Instant::now();
+    let outcome = next.call(req).await;
+    // 中间件（例如未登录时的重定向）直接返回的错误同样会被转换为响应
+    let status = match &outcome {
+        Ok(response) => response.status(),
+        Err(e) => e.as_response_error().status_code(),
+    };
+    let labels = [method.as_str(), route.as_str(), status.as_str()];
+    METRICS.http_requests_total.with_label_values(&labels).inc();
+    METRICS
+        .http_request_duration_seconds
+        .with_label_values(&labels)
+        .observe(start.elapsed().as_secs_f64());
+    outcome
+}
+
+/// 邮件发送的结果分类
+fn email_outcome(outcome: &Result<SentEmail, EmailError>) -> &'static str {
+    match outcome {
+        Ok(_) => "success",
+        Err(e) if e.is_retryable() => "retryable_error",
+        Err(_) => "permanent_error",
+    }
+}
+
+/// 记录单封邮件的发送结果与耗时
+pub fn record_email_send(outcome: &Result<SentEmail, EmailError>, elapsed: Duration) {
+    let labels = ["single", email_outcome(outcome)];
+    METRICS.email_sends_total.with_label_values(&labels).inc();
+    METRICS
+        .email_send_duration_seconds
+        .with_label_values(&labels)
+        .observe(elapsed.as_secs_f64());
+}
+
+/// 记录批量发送中每封邮件的结果；耗时按整批中最差的结果记录一次
+pub fn record_email_batch(outcomes: &[Result<SentEmail, EmailError>], elapsed: Duration) {
+    let mut worst = "success";
+    for outcome in outcomes {
+        let outcome = email_outcome(outcome);
+        METRICS
+            .email_sends_total
+            .with_label_values(&["batch", outcome])
+            .inc();
+        if outcome == "retryable_error" || worst == "success" {
+            worst = outcome;
+        }
+    }
+    METRICS
+        .email_send_duration_seconds
+        .with_label_values(&["batch", worst])
+        .observe(elapsed.as_secs_f64());
+}
+
+/// 采集连接池的当前状态：连接数与空闲连接数
+fn record_pool_metrics(pool: &PgPool) {
+    METRICS.db_pool_connections.set(pool.size().into());
+    METRICS.db_pool_idle_connections.set(pool.num_idle() as i64);
+    METRICS
+        .db_pool_max_connections
+        .set(pool.options().get_max_connections().into());
+}
+
+/// 开启事务，并记录从连接池获取连接所等待的时间
+pub async fn begin_transaction(
+    pool: &PgPool,
+) -> Result<Transaction<'static, Postgres>, sqlx::Error> {
+    let start = Instant::now();
+    let transaction = pool.begin().await;
+    METRICS
+        .db_pool_acquire_duration_seconds
+        .observe(start.elapsed().as_secs_f64());
+    transaction
+}
+
+/// 以 Prometheus 文本格式输出所有指标，只在单独的管理端口上提供
+pub async fn metrics(pool: web::Data<PgPool>) -> HttpResponse {
+    record_pool_metrics(&pool);
+    let encoder = TextEncoder::new();
+    let mut body = Vec::new();
+    if let Err(e) = encoder.encode(&METRICS.registry.gather(), &mut body) {
+        tracing::error!(error.message = %e, "Failed to encode metrics.");
+        return HttpResponse::InternalServerError().finish();
+    }
+    HttpResponse::Ok()
+        .content_type(encoder.format_type())
+        .body(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::email_client::{EmailError, SentEmail};
+    use crate::metrics::email_outcome;
+
+    #[test]
+    fn email_outcomes_follow_the_retry_classification() {
+        let transient: Result<SentEmail, EmailError> =
+            Err(EmailError::Transient(anyhow::anyhow!("timeout")));
+        let permanent: Result<SentEmail, EmailError> =
+            Err(EmailError::Permanent(anyhow::anyhow!("rejected")));
+
+        assert_eq!(email_outcome(&Ok(SentEmail::default())), "success");
+        assert_eq!(email_outcome(&transient), "retryable_error");
+        assert_eq!(email_outcome(&permanent), "permanent_error");
+    }
+}
@@ -0,0 +1,215 @@
+use crate::utils::e500;
+use actix_web::body::{to_bytes, BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::error::InternalError;
+use actix_web::http::header::{HeaderName, HeaderValue, CONTENT_TYPE};
+use actix_web::middleware::Next;
+use actix_web::{HttpMessage, HttpResponse};
+use uuid::Uuid;
+
+/// 请求与响应中携带请求 id 的请求头
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// 上游传入的请求 id 的最大长度
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+/// 一次请求的 id：沿用上游网关传入的 `X-Request-Id`，没有或不合法时重新生成，
+/// 会写入根 span（从而出现在每条日志中）、响应头以及 `problem+json` 错误响应体
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RequestId(String);
+
+impl RequestId {
+    pub fn generate() -> Self {
+        Self(Uuid::new_v4().to_string())
+    }
+
+    /// 只接受 1 到 128 个字母、数字与 `-`、`_`、`.`、`:`，避免把任意内容写进日志与响应头
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let is_valid = !s.is_empty()
+            && s.len() <= MAX_REQUEST_ID_LENGTH
+            && s.chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'));
+        if is_valid {
+            Ok(Self(s.to_owned()))
+        } else {
+            Err(format!("{:?} is not a valid request id.", s))
+        }
+    }
+}
+
+impl AsRef<str> for RequestId {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for RequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// 确定请求 id 并写入请求扩展，供根 span 使用；所有响应（包括错误响应）都带上 `X-Request-Id`。
+/// 必须注册在 `TracingLogger` 之外
+pub async fn propagate_request_id(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    let request_id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| RequestId::parse(value).ok())
+        .unwrap_or_else(RequestId::generate);
+    req.extensions_mut().insert(request_id.clone());
+    match next.call(req).await {
+        Ok(response) => {
+            let (http_request, response) = response.map_into_boxed_body().into_parts();
+            let response = with_request_id(response, &request_id).await?;
+            Ok(ServiceResponse::new(http_request, response))
+        }
+        // 中间件直接返回的错误（例如未登录时的重定向）同样需要带上请求 id
+        Err(e) => {
+            let response = with_request_id(e.error_response(), &request_id).await?;
+            Err(InternalError::from_response(e, response).into())
+        }
+    }
+}
+
+/// 加上 `X-Request-Id` 响应头；`application/problem+json` 响应体中再加入 `request_id` 扩展字段，
+/// 便于用户反馈问题时提供
+async fn with_request_id(
+    response: HttpResponse,
+    request_id: &RequestId,
+) -> Result<HttpResponse, actix_web::Error> {
+    let is_problem = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .is_some_and(|value| value.as_bytes().starts_with(b"application/problem+json"));
+    let mut response = if is_problem {
+        let (response, body) = response.into_parts();
+        let body = to_bytes(body).await.map_err(e500)?;
+        let body = match serde_json::from_slice::<serde_json::Value>(&body) {
+            Ok(serde_json::Value::Object(mut problem)) => {
+                problem.insert("request_id".into(), request_id.as_ref().into());
+                serde_json::to_vec(&problem).map_err(e500)?.into()
+            }
+            _ => body,
+        };
+        response.set_body(BoxBody::new(body))
+    } else {
+        response
+    };
+    response.headers_mut().insert(
+        REQUEST_ID_HEADER,
+        HeaderValue::from_str(request_id.as_ref()).expect("Request ids are valid header values."),
+    );
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::configuration::LogFormat;
+    use crate::request_id::{propagate_request_id, RequestId};
+    use crate::telemetry::{get_subscriber, RequestRootSpanBuilder};
+    use actix_web::middleware::from_fn;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::{web, App, HttpResponse};
+    use claim::{assert_err, assert_ok};
+    use opentelemetry_sdk::trace::TracerProvider;
+    use std::sync::{Arc, Mutex};
+    use tracing_actix_web::TracingLogger;
+
+    #[test]
+    fn typical_request_ids_are_accepted() {
+        for id in [
+            "0b8c1a6e-6b2c-4d3c-8a4e-2f1d9c7b5a3e",
+            "req_01HZX3J4K5",
+            "gateway:1-5759e988.bd862e3fe1be46a9",
+        ] {
+            assert_ok!(RequestId::parse(id));
+        }
+    }
+
+    #[test]
+    fn unsafe_or_oversized_request_ids_are_rejected() {
+        for id in [
+            "",
+            "has space",
+            "line\nbreak",
+            "<script>",
+            "中文",
+            &"a".repeat(129),
+        ] {
+            assert_err!(RequestId::parse(id));
+        }
+    }
+
+    /// 日志写入内存，便于检查日志记录中的字段
+    #[derive(Clone, Default)]
+    struct Logs(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Logs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    async fn log_something() -> HttpResponse {
+        tracing::info!("Handling the request.");
+        HttpResponse::Ok().finish()
+    }
+
+    #[actix_web::test]
+    async fn every_log_record_carries_the_request_id() {
+        let logs = Logs::default();
+        let writer = logs.clone();
+        let (subscriber, _) = get_subscriber(
+            "test".into(),
+            "info".into(),
+            LogFormat::Bunyan,
+            false,
+            move || writer.clone(),
+            &TracerProvider::default(),
+        );
+        let _guard = tracing::subscriber::set_default(subscriber);
+        let app = init_service(
+            App::new()
+                .wrap(TracingLogger::<RequestRootSpanBuilder>::new())
+                .wrap(from_fn(propagate_request_id))
+                .route("/", web::get().to(log_something)),
+        )
+        .await;
+
+        let request = TestRequest::get()
+            .uri("/")
+            .insert_header(("X-Request-Id", "support-ticket-42"))
+            .to_request();
+        let response = call_service(&app, request).await;
+
+        assert_eq!(
+            response.headers().get("X-Request-Id").unwrap(),
+            "support-ticket-42"
+        );
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        let records: Vec<serde_json::Value> = logs
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert!(
+            records.iter().any(|r| r["msg"]
+                .as_str()
+                .unwrap()
+                .ends_with("Handling the request.")),
+            "{}",
+            logs
+        );
+        for record in records {
+            assert_eq!(record["request_id"], "support-ticket-42", "{}", record);
+        }
+    }
+}
@@ -0,0 +1,13 @@
+mod dashboard;
+mod dead_letters;
+mod engagement;
+mod log_level;
+mod logout;
+mod password;
+
+pub use dashboard::*;
+pub use dead_letters::*;
+pub use engagement::*;
+pub use log_level::*;
+pub use logout::*;
+pub use password::*;
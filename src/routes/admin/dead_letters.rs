@@ -0,0 +1,96 @@
+use crate::metrics::begin_transaction;
+use crate::utils::e500;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(serde::Serialize)]
+pub struct DeadLetter {
+    pub newsletter_issue_id: Uuid,
+    pub subscriber_email: String,
+    pub n_retries: i16,
+    pub last_error: String,
+    pub failed_at: DateTime<Utc>,
+}
+
+/// 列出所有投递失败、等待人工处理的任务
+pub async fn list_dead_letters(pool: web::Data<PgPool>) -> Result<HttpResponse, actix_web::Error> {
+    let dead_letters = get_dead_letters(&pool).await.map_err(e500)?;
+    Ok(HttpResponse::Ok().json(dead_letters))
+}
+
+#[derive(serde::Deserialize)]
+pub struct RequeueFormData {
+    newsletter_issue_id: Uuid,
+    subscriber_email: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct RequeueReport {
+    pub requeued: u64,
+}
+
+/// 将死信重新放回投递队列，不指定邮箱时重新投递该期简报的全部死信
+#[tracing::instrument(skip(form, pool), fields(newsletter_issue_id = %form.newsletter_issue_id))]
+pub async fn requeue_dead_letters(
+    form: web::Form<RequeueFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let requeued = requeue(
+        &pool,
+        form.newsletter_issue_id,
+        form.subscriber_email.as_deref(),
+    )
+    .await
+    .map_err(e500)?;
+    Ok(HttpResponse::Ok().json(RequeueReport { requeued }))
+}
+
+#[tracing::instrument(name = "Get dead letters", skip(pool))]
+async fn get_dead_letters(pool: &PgPool) -> Result<Vec<DeadLetter>, anyhow::Error> {
+    let dead_letters = sqlx::query_as!(
+        DeadLetter,
+        r#"
+        SELECT newsletter_issue_id, subscriber_email, n_retries, last_error, failed_at
+        FROM issue_delivery_dead_letters
+        ORDER BY failed_at DESC
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve dead letters.")?;
+    Ok(dead_letters)
+}
+
+#[tracing::instrument(name = "Requeue dead letters", skip(pool, subscriber_email))]
+async fn requeue(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    subscriber_email: Option<&str>,
+) -> Result<u64, anyhow::Error> {
+    let mut transaction = begin_transaction(pool).await?;
+    let requeued = sqlx::query!(
+        r#"
+        WITH requeued AS (
+            DELETE FROM issue_delivery_dead_letters
+            WHERE
+                newsletter_issue_id = $1 AND
+                ($2::TEXT IS NULL OR subscriber_email = $2)
+            RETURNING newsletter_issue_id, subscriber_email
+        )
+        INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email)
+        SELECT newsletter_issue_id, subscriber_email FROM requeued
+        ON CONFLICT DO NOTHING
+        "#,
+        newsletter_issue_id,
+        subscriber_email,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to requeue dead letters.")?
+    .rows_affected();
+    transaction.commit().await?;
+    Ok(requeued)
+}
@@ -0,0 +1,49 @@
+use crate::utils::e500;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// 单期简报的打开与点击统计
+#[derive(serde::Serialize)]
+pub struct IssueEngagement {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    pub published_at: DateTime<Utc>,
+    pub opens: i64,
+    pub unique_opens: i64,
+    pub clicks: i64,
+    pub unique_clicks: i64,
+}
+
+/// 按期列出打开与点击的次数，以及打开、点击过的订阅者人数
+pub async fn list_engagement(pool: web::Data<PgPool>) -> Result<HttpResponse, actix_web::Error> {
+    let engagement = get_engagement(&pool).await.map_err(e500)?;
+    Ok(HttpResponse::Ok().json(engagement))
+}
+
+#[tracing::instrument(name = "Get engagement", skip(pool))]
+async fn get_engagement(pool: &PgPool) -> Result<Vec<IssueEngagement>, anyhow::Error> {
+    let engagement = sqlx::query_as!(
+        IssueEngagement,
+        r#"
+        SELECT
+            i.newsletter_issue_id,
+            i.title,
+            i.published_at,
+            COUNT(e.id) FILTER (WHERE e.event_type = 'open') AS "opens!",
+            COUNT(DISTINCT e.subscriber_id) FILTER (WHERE e.event_type = 'open') AS "unique_opens!",
+            COUNT(e.id) FILTER (WHERE e.event_type = 'click') AS "clicks!",
+            COUNT(DISTINCT e.subscriber_id) FILTER (WHERE e.event_type = 'click') AS "unique_clicks!"
+        FROM newsletter_issues i
+        LEFT JOIN tracking_events e ON e.newsletter_issue_id = i.newsletter_issue_id
+        GROUP BY i.newsletter_issue_id
+        ORDER BY i.published_at DESC
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve engagement statistics.")?;
+    Ok(engagement)
+}
@@ -0,0 +1,46 @@
+use crate::authentication::middleware::UserId;
+use crate::telemetry::{LogFilterError, LogFilterHandle};
+use crate::utils::{e500, ProblemDetails};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse};
+
+#[derive(serde::Serialize)]
+pub struct LogLevel {
+    pub filter: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct LogLevelFormData {
+    filter: String,
+}
+
+/// 当前生效的日志过滤规则
+pub async fn log_level(
+    log_filter: web::Data<LogFilterHandle>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let filter = log_filter.current().map_err(e500)?;
+    Ok(HttpResponse::Ok().json(LogLevel { filter }))
+}
+
+/// 替换日志过滤规则（`EnvFilter` 语法，例如 `info,sqlx=debug`），立即生效，重启后恢复为配置中的值
+#[tracing::instrument(skip(form, log_filter, user_id), fields(user_id = %*user_id))]
+pub async fn change_log_level(
+    form: web::Form<LogLevelFormData>,
+    log_filter: web::Data<LogFilterHandle>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let previous = log_filter.current().map_err(e500)?;
+    match log_filter.set(&form.filter) {
+        Ok(()) => {}
+        Err(LogFilterError::InvalidFilter(e)) => {
+            return Ok(ProblemDetails::new(StatusCode::BAD_REQUEST)
+                .detail("The log filter is not valid.")
+                .invalid_param("filter", e.to_string())
+                .to_response());
+        }
+        Err(e) => return Err(e500(e)),
+    }
+    let filter = log_filter.current().map_err(e500)?;
+    tracing::warn!(previous, filter, "The log filter has been changed.");
+    Ok(HttpResponse::Ok().json(LogLevel { filter }))
+}
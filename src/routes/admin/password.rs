@@ -0,0 +1,98 @@
+use crate::authentication::middleware::UserId;
+use crate::authentication::{change_password as store_new_password, validate_credentials};
+use crate::authentication::{AuthError, Credentials};
+use crate::routes::admin::dashboard::get_username;
+use crate::utils::{e500, see_other};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use secrecy::{ExposeSecret, SecretBox};
+use sqlx::PgPool;
+use std::fmt::Write;
+
+#[derive(serde::Deserialize)]
+pub struct PasswordFormData {
+    current_password: SecretBox<String>,
+    new_password: SecretBox<String>,
+    new_password_check: SecretBox<String>,
+}
+
+pub async fn change_password_form(
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Change Password</title>
+</head>
+<body>
+    {msg_html}
+    <form action="/admin/password" method="post">
+        <label>Current password
+            <input type="password" placeholder="Enter current password" name="current_password">
+        </label>
+        <br>
+        <label>New password
+            <input type="password" placeholder="Enter new password" name="new_password">
+        </label>
+        <br>
+        <label>Confirm new password
+            <input type="password" placeholder="Type the new password again" name="new_password_check">
+        </label>
+        <br>
+        <button type="submit">Change password</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
+
+pub async fn change_password(
+    form: web::Form<PasswordFormData>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    if form.new_password.expose_secret() != form.new_password_check.expose_secret() {
+        FlashMessage::error(
+            "You entered two different new passwords - the field values must match.",
+        )
+        .send();
+        return Ok(see_other("/admin/password"));
+    }
+    let new_password_length = form.new_password.expose_secret().chars().count();
+    if !(12..=128).contains(&new_password_length) {
+        FlashMessage::error("The new password must be between 12 and 128 characters long.").send();
+        return Ok(see_other("/admin/password"));
+    }
+
+    let username = get_username(*user_id, &pool).await.map_err(e500)?;
+    let form = form.into_inner();
+    let credentials = Credentials {
+        username,
+        password: form.current_password,
+    };
+    if let Err(e) = validate_credentials(credentials, &pool).await {
+        return match e {
+            AuthError::InvalidCredentials(_) => {
+                FlashMessage::error("The current password is incorrect.").send();
+                Ok(see_other("/admin/password"))
+            }
+            AuthError::UnexpectedError(_) => Err(e500(e)),
+        };
+    }
+    store_new_password(*user_id, form.new_password, &pool)
+        .await
+        .map_err(e500)?;
+    FlashMessage::info("Your password has been changed.").send();
+    Ok(see_other("/admin/password"))
+}
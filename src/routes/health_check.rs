@@ -1,5 +1,146 @@
-use actix_web::HttpResponse;
+use crate::configuration::HealthSettings;
+use crate::email_client::EmailClient;
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+use std::collections::HashSet;
+use std::future::Future;
+use std::time::{Duration, Instant};
 
 pub async fn health_check() -> HttpResponse {
     HttpResponse::Ok().finish()
 }
+
+/// 单个依赖项的检查结果
+#[derive(serde::Serialize, Debug)]
+pub struct DependencyStatus {
+    pub status: Status,
+    pub latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pending_migrations: Option<usize>,
+}
+
+#[derive(serde::Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Up,
+    Down,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct Readiness {
+    pub status: Status,
+    pub checks: Checks,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct Checks {
+    pub database: DependencyStatus,
+    pub migrations: DependencyStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email_provider: Option<DependencyStatus>,
+}
+
+/// 存活探针：进程能够处理请求即可，不检查任何依赖
+pub async fn health_live() -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({ "status": Status::Up }))
+}
+
+/// 就绪探针：数据库可用、迁移已全部执行，且（按配置）邮件服务可达时才返回 200，否则返回 503
+#[tracing::instrument(name = "Readiness check", skip_all)]
+pub async fn health_ready(
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    settings: web::Data<HealthSettings>,
+) -> HttpResponse {
+    let timeout = settings.timeout();
+    let database = check(timeout, async {
+        sqlx::query("SELECT 1").execute(pool.get_ref()).await?;
+        Ok(())
+    })
+    .await;
+    let mut pending = None;
+    let mut migrations = check(timeout, async {
+        let n = pending_migrations(&pool).await?;
+        pending = Some(n);
+        if n > 0 {
+            anyhow::bail!("{} migration(s) have not been applied.", n);
+        }
+        Ok(())
+    })
+    .await;
+    migrations.pending_migrations = pending;
+    let email_provider = if settings.check_email_provider {
+        Some(
+            check(timeout, async {
+                email_client.ping().await?;
+                Ok(())
+            })
+            .await,
+        )
+    } else {
+        None
+    };
+
+    let all_up = [Some(&database), Some(&migrations), email_provider.as_ref()]
+        .into_iter()
+        .flatten()
+        .all(|c| c.status == Status::Up);
+    let readiness = Readiness {
+        status: if all_up { Status::Up } else { Status::Down },
+        checks: Checks {
+            database,
+            migrations,
+            email_provider,
+        },
+    };
+    if all_up {
+        HttpResponse::Ok().json(readiness)
+    } else {
+        tracing::warn!(?readiness, "The instance is not ready.");
+        HttpResponse::ServiceUnavailable().json(readiness)
+    }
+}
+
+/// 在超时限制内执行一次依赖检查，并记录耗时
+async fn check<F>(timeout: Duration, f: F) -> DependencyStatus
+where
+    F: Future<Output = Result<(), anyhow::Error>>,
+{
+    let start = Instant::now();
+    let outcome = match tokio::time::timeout(timeout, f).await {
+        Ok(outcome) => outcome,
+        Err(_) => Err(anyhow::anyhow!("Timed out after {:?}.", timeout)),
+    };
+    let latency_ms = start.elapsed().as_millis() as u64;
+    match outcome {
+        Ok(()) => DependencyStatus {
+            status: Status::Up,
+            latency_ms,
+            error: None,
+            pending_migrations: None,
+        },
+        Err(e) => DependencyStatus {
+            status: Status::Down,
+            latency_ms,
+            error: Some(e.to_string()),
+            pending_migrations: None,
+        },
+    }
+}
+
+/// 编译进二进制的迁移中尚未在数据库中成功执行的数量
+async fn pending_migrations(pool: &PgPool) -> Result<usize, anyhow::Error> {
+    let applied: HashSet<i64> =
+        sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success = true")
+            .fetch_all(pool)
+            .await?
+            .into_iter()
+            .collect();
+    let pending = sqlx::migrate!("./migrations")
+        .iter()
+        .filter(|m| !m.migration_type.is_down_migration() && !applied.contains(&m.version))
+        .count();
+    Ok(pending)
+}
@@ -0,0 +1,96 @@
+use crate::authentication::{validate_credentials, AuthError, Credentials};
+use crate::session_state::TypedSession;
+use crate::telemetry::redact;
+use crate::utils::see_other;
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use secrecy::SecretBox;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+#[derive(serde::Deserialize)]
+pub struct LoginFormData {
+    username: String,
+    password: SecretBox<String>,
+}
+
+pub async fn login_form(flash_messages: IncomingFlashMessages) -> HttpResponse {
+    let mut error_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(error_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(login_page(&error_html))
+}
+
+#[tracing::instrument(
+    skip(form, pool, session),
+    fields(username = tracing::field::Empty, user_id = tracing::field::Empty)
+)]
+pub async fn login(
+    form: web::Form<LoginFormData>,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+) -> HttpResponse {
+    let form = form.into_inner();
+    let credentials = Credentials {
+        username: form.username,
+        password: form.password,
+    };
+    tracing::Span::current().record(
+        "username",
+        tracing::field::display(redact(&credentials.username)),
+    );
+    match validate_credentials(credentials, &pool).await {
+        Ok(user_id) => {
+            tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+            // 登录后更换 session key，防止会话固定攻击
+            session.renew();
+            if let Err(e) = session.insert_user_id(user_id) {
+                tracing::error!(error.cause_chain = ?e, "Failed to store the user id in the session.");
+                return login_redirect("Something went wrong. Please try again.");
+            }
+            see_other("/admin/dashboard")
+        }
+        Err(AuthError::InvalidCredentials(e)) => {
+            tracing::warn!(error.cause_chain = ?e, "Failed to authenticate the user.");
+            login_redirect("Authentication failed.")
+        }
+        Err(AuthError::UnexpectedError(e)) => {
+            tracing::error!(error.cause_chain = ?e, "Failed to validate credentials.");
+            login_redirect("Something went wrong. Please try again.")
+        }
+    }
+}
+
+/// 通过一次性 flash cookie 携带错误信息并跳回登录页
+fn login_redirect(message: &str) -> HttpResponse {
+    FlashMessage::error(message).send();
+    see_other("/login")
+}
+
+fn login_page(error_html: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Login</title>
+</head>
+<body>
+    {error_html}
+    <form action="/login" method="post">
+        <label>Username
+            <input type="text" placeholder="Enter Username" name="username">
+        </label>
+        <label>Password
+            <input type="password" placeholder="Enter Password" name="password">
+        </label>
+        <button type="submit">Login</button>
+    </form>
+</body>
+</html>"#
+    )
+}
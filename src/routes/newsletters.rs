@@ -0,0 +1,178 @@
+use crate::authentication::{basic_authentication, validate_credentials, AuthError};
+use crate::idempotency::{execute_idempotently, idempotency_key_from_request};
+use crate::metrics::begin_transaction;
+use crate::telemetry::redact;
+use actix_web::http::header::{HeaderValue, WWW_AUTHENTICATE};
+use actix_web::{web, HttpRequest, HttpResponse};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct BodyData {
+    title: String,
+    content: Content,
+}
+
+#[derive(serde::Deserialize)]
+pub struct Content {
+    html: String,
+    text: String,
+}
+
+/// 发布接口的响应：投递在后台异步完成
+#[derive(serde::Serialize)]
+pub struct PublishAccepted {
+    pub newsletter_issue_id: Uuid,
+    pub enqueued: u64,
+}
+
+#[tracing::instrument(
+    name = "Publish a newsletter issue",
+    skip(body, pool, request),
+    fields(title = %body.title, username = tracing::field::Empty, user_id = tracing::field::Empty)
+)]
+pub async fn publish_newsletter(
+    body: web::Json<BodyData>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> HttpResponse {
+    let credentials = match basic_authentication(request.headers()) {
+        Ok(credentials) => credentials,
+        Err(e) => {
+            tracing::warn!(error.cause_chain = ?e, "Rejected a request without valid 'Basic' credentials.");
+            return basic_auth_challenge();
+        }
+    };
+    tracing::Span::current().record(
+        "username",
+        tracing::field::display(redact(&credentials.username)),
+    );
+    let user_id = match validate_credentials(credentials, &pool).await {
+        Ok(user_id) => user_id,
+        Err(AuthError::InvalidCredentials(e)) => {
+            tracing::warn!(error.cause_chain = ?e, "Failed to authenticate the publisher.");
+            return basic_auth_challenge();
+        }
+        Err(AuthError::UnexpectedError(e)) => {
+            tracing::error!(error.cause_chain = ?e, "Failed to validate credentials.");
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    let idempotency_key = match idempotency_key_from_request(&request) {
+        Ok(idempotency_key) => idempotency_key,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    let scope = format!("user:{}", user_id);
+    match execute_idempotently(&pool, idempotency_key, &scope, || async {
+        Ok::<_, anyhow::Error>(enqueue_issue(&body, &pool).await)
+    })
+    .await
+    {
+        Ok(response) => response,
+        Err(e) => {
+            tracing::error!(error.cause_chain = ?e, "Failed to process the idempotency key.");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// 保存这一期简报并为每个已确认的订阅者生成一个投递任务，由后台 worker 负责发送
+async fn enqueue_issue(body: &BodyData, pool: &PgPool) -> HttpResponse {
+    let mut transaction = match begin_transaction(pool).await {
+        Ok(transaction) => transaction,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let issue_id = match insert_newsletter_issue(
+        &mut transaction,
+        &body.title,
+        &body.content.text,
+        &body.content.html,
+    )
+    .await
+    {
+        Ok(issue_id) => issue_id,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let enqueued = match enqueue_delivery_tasks(&mut transaction, issue_id).await {
+        Ok(enqueued) => enqueued,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    if transaction.commit().await.is_err() {
+        return HttpResponse::InternalServerError().finish();
+    }
+    HttpResponse::Accepted().json(PublishAccepted {
+        newsletter_issue_id: issue_id,
+        enqueued,
+    })
+}
+
+/// 认证失败时返回 401，并通过 `WWW-Authenticate` 提示客户端使用 Basic 认证
+fn basic_auth_challenge() -> HttpResponse {
+    let mut response = HttpResponse::Unauthorized().finish();
+    let header_value = HeaderValue::from_str(r#"Basic realm="publish""#).unwrap();
+    response
+        .headers_mut()
+        .insert(WWW_AUTHENTICATE, header_value);
+    response
+}
+
+#[tracing::instrument(skip_all)]
+async fn insert_newsletter_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    title: &str,
+    text_content: &str,
+    html_content: &str,
+) -> Result<Uuid, sqlx::Error> {
+    let newsletter_issue_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id,
+            title,
+            text_content,
+            html_content,
+            published_at
+        )
+        VALUES ($1, $2, $3, $4, now())
+        "#,
+        newsletter_issue_id,
+        title,
+        text_content,
+        html_content,
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(newsletter_issue_id)
+}
+
+#[tracing::instrument(skip_all)]
+async fn enqueue_delivery_tasks(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue (
+            newsletter_issue_id,
+            subscriber_email
+        )
+        SELECT $1, email
+        FROM subscriptions
+        WHERE status = 'confirmed'
+        "#,
+        newsletter_issue_id,
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(result.rows_affected())
+}
@@ -1,58 +1,277 @@
 use crate::domain::{
-    new_subscriber::NewSubscriber, subscriber_email::SubscriberEmail,
-    subscriber_name::SubscriberName,
+    new_subscriber::NewSubscriber,
+    subscriber_email::{SubscriberEmail, SubscriberEmailError},
+    subscriber_name::{SubscriberName, SubscriberNameError},
+    unsubscribe_token::UnsubscribeLinks,
 };
-use actix_web::{web, HttpResponse};
+use crate::email_client::{EmailClient, EmailError, EmailTemplate};
+use crate::idempotency::{execute_idempotently, idempotency_key_from_request};
+use crate::metrics::begin_transaction;
+use crate::startup::ApplicationBaseUrl;
+use crate::telemetry::redact;
+use crate::utils::{error_chain_fmt, ProblemDetails};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
 use chrono::Utc;
-use sqlx::PgPool;
+use minijinja::context;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use sha2::{Digest, Sha256};
+use sqlx::{PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
+#[derive(thiserror::Error)]
+pub enum SubscribeError {
+    #[error("The subscriber name is invalid.")]
+    InvalidName(#[source] SubscriberNameError),
+    #[error("The subscriber email is invalid.")]
+    InvalidEmail(#[source] SubscriberEmailError),
+    #[error("The idempotency key is invalid.")]
+    InvalidIdempotencyKey(#[source] anyhow::Error),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for SubscribeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for SubscribeError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            SubscribeError::InvalidName(_)
+            | SubscribeError::InvalidEmail(_)
+            | SubscribeError::InvalidIdempotencyKey(_) => StatusCode::BAD_REQUEST,
+            SubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let problem = ProblemDetails::new(self.status_code());
+        // 服务端错误的细节只出现在日志里
+        let problem = match self {
+            SubscribeError::InvalidName(e) => problem
+                .detail(self.to_string())
+                .invalid_param("name", e.to_string()),
+            SubscribeError::InvalidEmail(e) => problem
+                .detail(self.to_string())
+                .invalid_param("email", e.to_string()),
+            SubscribeError::InvalidIdempotencyKey(e) => problem
+                .detail(self.to_string())
+                .invalid_param("Idempotency-Key", e.to_string()),
+            SubscribeError::UnexpectedError(_) => problem,
+        };
+        problem.to_response()
+    }
+}
+
 #[tracing::instrument(
     name = "Adding a new subscriber",
-    skip(form, pool),
+    skip(form, pool, email_client, base_url, unsubscribe_links, request),
     fields(
-        subscriber_email = %form.email,
-        subscriber_name = %form.name
+        subscriber_email = %redact(&form.email),
+        subscriber_name = %redact(&form.name)
     )
 )]
-pub async fn subscribe(form: web::Form<FormData>, pool: web::Data<PgPool>) -> HttpResponse {
-    let new_subscriber = match form.0.try_into() {
-        Ok(new_subscriber) => new_subscriber,
-        Err(_) => return HttpResponse::BadRequest().finish(),
+pub async fn subscribe(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    unsubscribe_links: web::Data<UnsubscribeLinks>,
+    request: HttpRequest,
+) -> Result<HttpResponse, SubscribeError> {
+    let idempotency_key =
+        idempotency_key_from_request(&request).map_err(SubscribeError::InvalidIdempotencyKey)?;
+    let scope = idempotency_scope(&form.email);
+    execute_idempotently(&pool, idempotency_key, &scope, || {
+        process_subscription(
+            form.into_inner(),
+            &pool,
+            &email_client,
+            &base_url.0,
+            &unsubscribe_links,
+        )
+    })
+    .await
+}
+
+/// 订阅接口无需登录，幂等键按邮箱划分空间：不同邮箱的请求即使复用同一个键，
+/// 也不会拿到别人的响应。只保存规范化邮箱的摘要，不落库明文地址。
+fn idempotency_scope(email: &str) -> String {
+    let digest = Sha256::digest(email.trim().to_lowercase().as_bytes());
+    format!("subscriptions:{:x}", digest)
+}
+
+async fn process_subscription(
+    form: FormData,
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+    unsubscribe_links: &UnsubscribeLinks,
+) -> Result<HttpResponse, SubscribeError> {
+    let new_subscriber: NewSubscriber = form.try_into()?;
+    let mut transaction = begin_transaction(pool)
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let inserted = insert_subscription(&mut transaction, &new_subscriber)
+        .await
+        .context("Failed to insert new subscriber in the database.")?;
+    let subscriber_id = match inserted {
+        Some(subscriber_id) => subscriber_id,
+        // 邮箱已存在：尚未确认的重新发送确认邮件，已确认或已被抑制的直接返回，
+        // 不向调用方透露该邮箱是否已经订阅
+        None => match get_pending_subscriber_id(&mut transaction, &new_subscriber)
+            .await
+            .context("Failed to look up an existing subscriber.")?
+        {
+            Some(subscriber_id) => subscriber_id,
+            None => return Ok(HttpResponse::Ok().finish()),
+        },
     };
-    match insert_subscription(&pool, &new_subscriber).await {
-        Ok(_) => HttpResponse::Ok().finish(),
-        Err(_) => HttpResponse::InternalServerError().finish(),
-    }
+    let subscription_token = generate_subscription_token();
+    store_token(&mut transaction, subscriber_id, &subscription_token)
+        .await
+        .context("Failed to store the confirmation token for a new subscriber.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store a new subscriber.")?;
+    send_confirmation_email(
+        email_client,
+        new_subscriber,
+        base_url,
+        &subscription_token,
+        &unsubscribe_links.for_subscriber(subscriber_id),
+    )
+    .await
+    .context("Failed to send a confirmation email.")?;
+    Ok(HttpResponse::Ok().finish())
 }
 
 #[tracing::instrument(
     name = "Saving new subscriber details in the database",
-    skip(new_subscriber, pool)
+    skip(new_subscriber, transaction)
 )]
+/// 邮箱已存在且未退订时不做任何修改，返回 `None`
 pub async fn insert_subscription(
-    pool: &PgPool,
+    transaction: &mut Transaction<'_, Postgres>,
     new_subscriber: &NewSubscriber,
-) -> Result<(), sqlx::Error> {
-    sqlx::query!(
+) -> Result<Option<Uuid>, sqlx::Error> {
+    // 已退订的邮箱重新订阅时沿用原来的记录，保留其订阅历史
+    let row = sqlx::query!(
         r#"
-        INSERT INTO subscriptions (id, email, name, subscribed_at) 
-        VALUES ($1, $2, $3, $4)
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES ($1, $2, $3, $4, 'pending_confirmation')
+        ON CONFLICT (email) DO UPDATE
+        SET
+            name = EXCLUDED.name,
+            subscribed_at = EXCLUDED.subscribed_at,
+            status = EXCLUDED.status
+        WHERE subscriptions.status = 'unsubscribed'
+        RETURNING id
         "#,
         Uuid::new_v4(),
         new_subscriber.email.as_ref(),
         new_subscriber.name.as_ref(),
         Utc::now(),
     )
-    .execute(pool)
-    .await
-    .map_err(|e| {
-        tracing::error!("Failed to execute query: {:?}", e);
-        e
-    })?;
+    .fetch_optional(&mut **transaction)
+    .await?;
+    Ok(row.map(|row| row.id))
+}
+
+#[tracing::instrument(name = "Get pending subscriber id", skip(new_subscriber, transaction))]
+async fn get_pending_subscriber_id(
+    transaction: &mut Transaction<'_, Postgres>,
+    new_subscriber: &NewSubscriber,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT id FROM subscriptions
+        WHERE email = $1 AND status = 'pending_confirmation'
+        "#,
+        new_subscriber.email.as_ref(),
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
+    Ok(row.map(|row| row.id))
+}
+
+/// 替换订阅者之前的令牌：重新发送确认邮件后，只有最新一封邮件中的链接有效
+#[tracing::instrument(
+    name = "Store subscription token in the database",
+    skip(subscription_token, transaction)
+)]
+pub async fn store_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    subscription_token: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"DELETE FROM subscription_tokens WHERE subscriber_id = $1"#,
+        subscriber_id,
+    )
+    .execute(&mut **transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO subscription_tokens (subscription_token, subscriber_id)
+        VALUES ($1, $2)
+        "#,
+        subscription_token,
+        subscriber_id,
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Send a confirmation email to a new subscriber",
+    skip(
+        email_client,
+        new_subscriber,
+        base_url,
+        subscription_token,
+        unsubscribe_url
+    )
+)]
+pub async fn send_confirmation_email(
+    email_client: &EmailClient,
+    new_subscriber: NewSubscriber,
+    base_url: &str,
+    subscription_token: &str,
+    unsubscribe_url: &str,
+) -> Result<(), EmailError> {
+    let confirmation_link = format!(
+        "{}/subscriptions/confirm?subscription_token={}",
+        base_url, subscription_token
+    );
+    email_client
+        .send_template(
+            &new_subscriber.email,
+            "Welcome!",
+            EmailTemplate::Confirmation,
+            context! { confirmation_link },
+            unsubscribe_url,
+        )
+        .await?;
     Ok(())
 }
 
+/// 生成一个大小写敏感的 25 位随机订阅令牌
+fn generate_subscription_token() -> String {
+    let mut rng = thread_rng();
+    std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(25)
+        .collect()
+}
+
 #[derive(serde::Deserialize)]
 pub struct FormData {
     pub email: String,
@@ -60,11 +279,11 @@ pub struct FormData {
 }
 
 impl TryFrom<FormData> for NewSubscriber {
-    type Error = String;
+    type Error = SubscribeError;
 
     fn try_from(form: FormData) -> Result<Self, Self::Error> {
-        let name = SubscriberName::parse(form.name)?;
-        let email = SubscriberEmail::parse(form.email)?;
+        let name = SubscriberName::parse(form.name).map_err(SubscribeError::InvalidName)?;
+        let email = SubscriberEmail::parse(form.email).map_err(SubscribeError::InvalidEmail)?;
         Ok(NewSubscriber { email, name })
     }
 }
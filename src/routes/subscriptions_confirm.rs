@@ -0,0 +1,110 @@
+use crate::domain::subscriber_email::SubscriberEmail;
+use crate::domain::unsubscribe_token::UnsubscribeLinks;
+use crate::email_client::{EmailClient, EmailTemplate};
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use minijinja::context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct Parameters {
+    subscription_token: String,
+}
+
+#[tracing::instrument(
+    name = "Confirm a pending subscriber",
+    skip(parameters, pool, email_client, unsubscribe_links)
+)]
+pub async fn confirm(
+    parameters: web::Query<Parameters>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    unsubscribe_links: web::Data<UnsubscribeLinks>,
+) -> HttpResponse {
+    let subscriber_id =
+        match get_subscriber_id_from_token(&pool, &parameters.subscription_token).await {
+            Ok(subscriber_id) => subscriber_id,
+            Err(_) => return HttpResponse::InternalServerError().finish(),
+        };
+    match subscriber_id {
+        None => HttpResponse::Unauthorized().finish(),
+        Some(subscriber_id) => {
+            let confirmed_email = match confirm_subscriber(&pool, subscriber_id).await {
+                Ok(confirmed_email) => confirmed_email,
+                Err(_) => return HttpResponse::InternalServerError().finish(),
+            };
+            // 只在第一次确认时发送欢迎邮件；订阅已经生效，发送失败只记录日志
+            if let Some(email) = confirmed_email {
+                let unsubscribe_url = unsubscribe_links.for_subscriber(subscriber_id);
+                if let Err(e) = send_welcome_email(&email_client, email, &unsubscribe_url).await {
+                    tracing::error!(
+                        error.cause_chain = ?e,
+                        error.message = %e,
+                        "Failed to send a welcome email."
+                    );
+                }
+            }
+            HttpResponse::Ok().finish()
+        }
+    }
+}
+
+/// 返回刚确认的订阅者的邮箱；订阅者此前已经确认过时返回 `None`
+#[tracing::instrument(name = "Mark subscriber as confirmed", skip(subscriber_id, pool))]
+pub async fn confirm_subscriber(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<String>, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"UPDATE subscriptions SET status = 'confirmed' WHERE id = $1 AND status = 'pending_confirmation' RETURNING email"#,
+        subscriber_id,
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(result.map(|r| r.email))
+}
+
+#[tracing::instrument(
+    name = "Send a welcome email to a confirmed subscriber",
+    skip(email_client, email, unsubscribe_url)
+)]
+pub async fn send_welcome_email(
+    email_client: &EmailClient,
+    email: String,
+    unsubscribe_url: &str,
+) -> Result<(), anyhow::Error> {
+    let email = SubscriberEmail::parse(email).context("The stored subscriber email is invalid.")?;
+    email_client
+        .send_template(
+            &email,
+            "You're subscribed!",
+            EmailTemplate::Welcome,
+            context! { unsubscribe_url },
+            unsubscribe_url,
+        )
+        .await?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Get subscriber_id from token", skip(subscription_token, pool))]
+pub async fn get_subscriber_id_from_token(
+    pool: &PgPool,
+    subscription_token: &str,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"SELECT subscriber_id FROM subscription_tokens WHERE subscription_token = $1"#,
+        subscription_token,
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(result.map(|r| r.subscriber_id))
+}
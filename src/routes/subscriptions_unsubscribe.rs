@@ -0,0 +1,151 @@
+use crate::domain::subscriber_email::SubscriberEmail;
+use crate::domain::unsubscribe_token::UnsubscribeLinks;
+use crate::email_client::{EmailClient, EmailTemplate};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use minijinja::context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct UnsubscribeParameters {
+    token: String,
+}
+
+/// 展示退订确认页；真正的退订只在 POST 时执行，避免邮件安全网关预取链接时误退订
+#[tracing::instrument(
+    name = "Show the unsubscribe form",
+    skip(parameters, unsubscribe_links)
+)]
+pub async fn unsubscribe_form(
+    parameters: web::Query<UnsubscribeParameters>,
+    unsubscribe_links: web::Data<UnsubscribeLinks>,
+) -> HttpResponse {
+    if let Err(e) = unsubscribe_links.verify(&parameters.token) {
+        tracing::warn!(error.message = %e, "Rejected an unsubscribe token.");
+        return HttpResponse::Unauthorized().finish();
+    }
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(unsubscribe_page(&parameters.token))
+}
+
+/// 退订：既处理确认页提交的表单，也处理邮件客户端按 RFC 8058 发起的一键退订请求
+#[tracing::instrument(
+    name = "Unsubscribe a subscriber",
+    skip(parameters, pool, email_client, unsubscribe_links),
+    fields(subscriber_id = tracing::field::Empty)
+)]
+pub async fn unsubscribe(
+    parameters: web::Query<UnsubscribeParameters>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    unsubscribe_links: web::Data<UnsubscribeLinks>,
+) -> HttpResponse {
+    let subscriber_id = match unsubscribe_links.verify(&parameters.token) {
+        Ok(subscriber_id) => subscriber_id,
+        Err(e) => {
+            tracing::warn!(error.message = %e, "Rejected an unsubscribe token.");
+            return HttpResponse::Unauthorized().finish();
+        }
+    };
+    tracing::Span::current().record("subscriber_id", tracing::field::display(&subscriber_id));
+    let unsubscribed_email = match mark_subscriber_as_unsubscribed(&pool, subscriber_id).await {
+        Ok(unsubscribed_email) => unsubscribed_email,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    // 重复退订时不再发送；退订已经生效，发送失败只记录日志
+    if let Some(email) = unsubscribed_email {
+        let unsubscribe_url = unsubscribe_links.for_subscriber(subscriber_id);
+        if let Err(e) = send_unsubscribe_confirmation(&email_client, email, &unsubscribe_url).await
+        {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to send an unsubscribe confirmation email."
+            );
+        }
+    }
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(unsubscribed_page())
+}
+
+/// 只修改状态而不删除记录，保留订阅历史；重复退订不会覆盖第一次的退订时间，
+/// 已被抑制的地址保持抑制状态。返回刚退订的订阅者的邮箱，状态没有变化时返回 `None`
+#[tracing::instrument(name = "Mark subscriber as unsubscribed", skip(pool))]
+pub async fn mark_subscriber_as_unsubscribed(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<String>, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET status = 'unsubscribed', unsubscribed_at = now()
+        WHERE id = $1 AND status NOT IN ('unsubscribed', 'suppressed')
+        RETURNING email
+        "#,
+        subscriber_id,
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(result.map(|r| r.email))
+}
+
+#[tracing::instrument(
+    name = "Send an unsubscribe confirmation email",
+    skip(email_client, email, unsubscribe_url)
+)]
+pub async fn send_unsubscribe_confirmation(
+    email_client: &EmailClient,
+    email: String,
+    unsubscribe_url: &str,
+) -> Result<(), anyhow::Error> {
+    let email = SubscriberEmail::parse(email).context("The stored subscriber email is invalid.")?;
+    email_client
+        .send_template(
+            &email,
+            "You have been unsubscribed",
+            EmailTemplate::UnsubscribeConfirmation,
+            context! {},
+            unsubscribe_url,
+        )
+        .await?;
+    Ok(())
+}
+
+fn unsubscribe_page(token: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Unsubscribe</title>
+</head>
+<body>
+    <p>Do you want to stop receiving our newsletter?</p>
+    <form action="/subscriptions/unsubscribe?token={token}" method="post">
+        <button type="submit">Unsubscribe</button>
+    </form>
+</body>
+</html>"#
+    )
+}
+
+fn unsubscribed_page() -> &'static str {
+    r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Unsubscribed</title>
+</head>
+<body>
+    <p>You have been unsubscribed. You will no longer receive our newsletter.</p>
+</body>
+</html>"#
+}
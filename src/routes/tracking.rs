@@ -0,0 +1,171 @@
+use crate::domain::tracking_token::{TrackingEvent, TrackingLinks, TrackingTarget};
+use crate::domain::unsubscribe_token::UnsubscribeLinks;
+use actix_web::http::header::{ContentType, CACHE_CONTROL, LOCATION};
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// 1x1 透明 GIF
+const PIXEL: &[u8] = b"GIF89a\x01\x00\x01\x00\x80\x00\x00\x00\x00\x00\xff\xff\xff\x21\xf9\x04\x01\x00\x00\x00\x00\x2c\x00\x00\x00\x00\x01\x00\x01\x00\x00\x02\x02\x44\x01\x00\x3b";
+
+/// 追踪像素与追踪链接的入口：记录打开或点击事件，然后返回像素图片或跳转到原始链接
+#[tracing::instrument(
+    name = "Record a tracking event",
+    skip(token, pool, tracking_links),
+    fields(newsletter_issue_id = tracing::field::Empty, subscriber_id = tracing::field::Empty)
+)]
+pub async fn track(
+    token: web::Path<String>,
+    pool: web::Data<PgPool>,
+    tracking_links: web::Data<TrackingLinks>,
+) -> HttpResponse {
+    let event = match tracking_links.verify(&token) {
+        Ok(event) => event,
+        Err(e) => {
+            tracing::warn!(error.message = %e, "Rejected a tracking token.");
+            return HttpResponse::NotFound().finish();
+        }
+    };
+    tracing::Span::current()
+        .record(
+            "newsletter_issue_id",
+            tracing::field::display(&event.newsletter_issue_id),
+        )
+        .record(
+            "subscriber_id",
+            tracing::field::display(&event.subscriber_id),
+        );
+    // 记录失败不应影响读者打开邮件或访问链接
+    if let Err(e) = record_tracking_event(&pool, &event).await {
+        tracing::error!(error.cause_chain = ?e, "Failed to record a tracking event.");
+    }
+    match event.target {
+        TrackingTarget::Open => HttpResponse::Ok()
+            .content_type("image/gif")
+            .insert_header((CACHE_CONTROL, "no-store"))
+            .body(PIXEL),
+        TrackingTarget::Click(url) => HttpResponse::Found()
+            .insert_header((LOCATION, url))
+            .finish(),
+    }
+}
+
+/// 已选择不被追踪的订阅者不记录任何事件
+#[tracing::instrument(skip_all)]
+async fn record_tracking_event(pool: &PgPool, event: &TrackingEvent) -> Result<(), sqlx::Error> {
+    let (event_type, url) = match &event.target {
+        TrackingTarget::Open => ("open", None),
+        TrackingTarget::Click(url) => ("click", Some(url.as_str())),
+    };
+    sqlx::query!(
+        r#"
+        INSERT INTO tracking_events (
+            id,
+            newsletter_issue_id,
+            subscriber_id,
+            event_type,
+            url,
+            occurred_at
+        )
+        SELECT $1, $2, id, $3, $4, now()
+        FROM subscriptions
+        WHERE id = $5 AND NOT tracking_opt_out
+        "#,
+        Uuid::new_v4(),
+        event.newsletter_issue_id,
+        event_type,
+        url,
+        event.subscriber_id,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+#[derive(serde::Deserialize)]
+pub struct TrackingOptOutParameters {
+    token: String,
+}
+
+/// 展示关闭追踪的确认页，与退订一样只在 POST 时执行
+#[tracing::instrument(
+    name = "Show the tracking opt-out form",
+    skip(parameters, unsubscribe_links)
+)]
+pub async fn tracking_opt_out_form(
+    parameters: web::Query<TrackingOptOutParameters>,
+    unsubscribe_links: web::Data<UnsubscribeLinks>,
+) -> HttpResponse {
+    if let Err(e) = unsubscribe_links.verify(&parameters.token) {
+        tracing::warn!(error.message = %e, "Rejected a tracking opt-out token.");
+        return HttpResponse::Unauthorized().finish();
+    }
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(tracking_opt_out_page(&parameters.token))
+}
+
+/// 关闭订阅者的打开与点击追踪，之后发送的简报不再包含追踪像素与追踪链接
+#[tracing::instrument(
+    name = "Opt a subscriber out of tracking",
+    skip(parameters, pool, unsubscribe_links),
+    fields(subscriber_id = tracing::field::Empty)
+)]
+pub async fn tracking_opt_out(
+    parameters: web::Query<TrackingOptOutParameters>,
+    pool: web::Data<PgPool>,
+    unsubscribe_links: web::Data<UnsubscribeLinks>,
+) -> HttpResponse {
+    let subscriber_id = match unsubscribe_links.verify(&parameters.token) {
+        Ok(subscriber_id) => subscriber_id,
+        Err(e) => {
+            tracing::warn!(error.message = %e, "Rejected a tracking opt-out token.");
+            return HttpResponse::Unauthorized().finish();
+        }
+    };
+    tracing::Span::current().record("subscriber_id", tracing::field::display(&subscriber_id));
+    if let Err(e) = sqlx::query!(
+        "UPDATE subscriptions SET tracking_opt_out = true WHERE id = $1",
+        subscriber_id,
+    )
+    .execute(pool.get_ref())
+    .await
+    {
+        tracing::error!("Failed to execute query: {:?}", e);
+        return HttpResponse::InternalServerError().finish();
+    }
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(tracking_opted_out_page())
+}
+
+fn tracking_opt_out_page(token: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Stop tracking</title>
+</head>
+<body>
+    <p>Do you want us to stop recording when you open our newsletter or click its links?</p>
+    <form action="/subscriptions/tracking_opt_out?token={token}" method="post">
+        <button type="submit">Stop tracking</button>
+    </form>
+</body>
+</html>"#
+    )
+}
+
+fn tracking_opted_out_page() -> &'static str {
+    r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Tracking disabled</title>
+</head>
+<body>
+    <p>We will no longer track when you open our newsletter or click its links.</p>
+</body>
+</html>"#
+}
@@ -0,0 +1,313 @@
+use crate::authentication::{basic_authentication, Credentials};
+use crate::metrics::begin_transaction;
+use crate::telemetry::redact;
+use crate::utils::{error_chain_fmt, ProblemDetails};
+use actix_web::http::header::{HeaderValue, WWW_AUTHENTICATE};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use secrecy::{ExposeSecret, SecretBox};
+use sha2::{Digest, Sha256};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+/// Postmark 调用回调接口时使用的 Basic 认证凭证，在 Postmark 后台配置到回调地址中
+pub struct WebhookCredentials {
+    pub username: String,
+    pub password: SecretBox<String>,
+}
+
+impl WebhookCredentials {
+    fn matches(&self, credentials: &Credentials) -> bool {
+        // 比较摘要而不是原文，比较的耗时不会泄露用户名或密码的内容；
+        // 用 `&` 而不是 `&&`，用户名是否正确也不会影响耗时
+        let digest = |s: &str| Sha256::digest(s.as_bytes());
+        let username_matches = digest(&credentials.username) == digest(&self.username);
+        let password_matches =
+            digest(credentials.password.expose_secret()) == digest(self.password.expose_secret());
+        username_matches & password_matches
+    }
+}
+
+/// Postmark 推送的事件，按 `RecordType` 区分；暂不处理的事件类型直接忽略
+#[derive(serde::Deserialize, Debug)]
+#[serde(tag = "RecordType")]
+pub enum PostmarkEvent {
+    Bounce(BounceEvent),
+    SpamComplaint(BounceEvent),
+    Delivery(DeliveryEvent),
+    #[serde(other)]
+    Other,
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct BounceEvent {
+    #[serde(rename = "MessageID")]
+    pub message_id: String,
+    #[serde(rename = "Type")]
+    pub bounce_type: String,
+    pub email: String,
+    pub bounced_at: DateTime<Utc>,
+}
+
+impl BounceEvent {
+    /// 地址不存在、被服务商停用或收件人投诉，继续发送只会损害发件信誉
+    fn is_hard(&self) -> bool {
+        matches!(
+            self.bounce_type.as_str(),
+            "HardBounce" | "BadEmailAddress" | "ManuallyDeactivated" | "SpamComplaint"
+        )
+    }
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct DeliveryEvent {
+    #[serde(rename = "MessageID")]
+    pub message_id: String,
+    pub recipient: String,
+    pub delivered_at: DateTime<Utc>,
+    #[serde(default)]
+    pub details: Option<String>,
+}
+
+/// 统一格式的投递事件，写入 `email_events` 表
+struct EmailEvent<'a> {
+    event_type: &'static str,
+    email: &'a str,
+    message_id: &'a str,
+    detail: Option<&'a str>,
+    occurred_at: DateTime<Utc>,
+    suppress: bool,
+}
+
+impl PostmarkEvent {
+    fn as_email_event(&self) -> Option<EmailEvent<'_>> {
+        match self {
+            PostmarkEvent::Bounce(e) => Some(EmailEvent {
+                event_type: "bounce",
+                email: &e.email,
+                message_id: &e.message_id,
+                detail: Some(&e.bounce_type),
+                occurred_at: e.bounced_at,
+                suppress: e.is_hard(),
+            }),
+            PostmarkEvent::SpamComplaint(e) => Some(EmailEvent {
+                event_type: "spam_complaint",
+                email: &e.email,
+                message_id: &e.message_id,
+                detail: Some(&e.bounce_type),
+                occurred_at: e.bounced_at,
+                suppress: true,
+            }),
+            PostmarkEvent::Delivery(e) => Some(EmailEvent {
+                event_type: "delivery",
+                email: &e.recipient,
+                message_id: &e.message_id,
+                detail: e.details.as_deref(),
+                occurred_at: e.delivered_at,
+                suppress: false,
+            }),
+            PostmarkEvent::Other => None,
+        }
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum WebhookError {
+    #[error("Authentication failed.")]
+    AuthError(#[source] anyhow::Error),
+    #[error("The webhook payload is invalid.")]
+    InvalidPayload(#[source] serde_json::Error),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for WebhookError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for WebhookError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            WebhookError::AuthError(_) => StatusCode::UNAUTHORIZED,
+            WebhookError::InvalidPayload(_) => StatusCode::BAD_REQUEST,
+            WebhookError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let problem = ProblemDetails::new(self.status_code());
+        match self {
+            WebhookError::AuthError(_) => {
+                let mut response = problem.to_response();
+                response.headers_mut().insert(
+                    WWW_AUTHENTICATE,
+                    HeaderValue::from_static(r#"Basic realm="webhooks""#),
+                );
+                response
+            }
+            // serde 的错误信息可能包含请求体中的片段，只返回出错的位置
+            WebhookError::InvalidPayload(e) => problem
+                .detail(self.to_string())
+                .invalid_param(
+                    "body",
+                    format!(
+                        "Not a valid Postmark event (line {}, column {}).",
+                        e.line(),
+                        e.column()
+                    ),
+                )
+                .to_response(),
+            WebhookError::UnexpectedError(_) => problem.to_response(),
+        }
+    }
+}
+
+/// 接收 Postmark 的退信、垃圾邮件投诉与送达回调：记录事件，并停止向硬退信或投诉的地址发送邮件
+#[tracing::instrument(
+    name = "Ingest a Postmark webhook",
+    skip_all,
+    fields(record_type = tracing::field::Empty, subscriber_email = tracing::field::Empty)
+)]
+pub async fn postmark_webhook(
+    request: HttpRequest,
+    body: web::Bytes,
+    pool: web::Data<PgPool>,
+    webhook_credentials: web::Data<WebhookCredentials>,
+) -> Result<HttpResponse, WebhookError> {
+    let credentials = basic_authentication(request.headers()).map_err(WebhookError::AuthError)?;
+    if !webhook_credentials.matches(&credentials) {
+        return Err(WebhookError::AuthError(anyhow::anyhow!(
+            "Invalid webhook credentials."
+        )));
+    }
+    let event: PostmarkEvent =
+        serde_json::from_slice(&body).map_err(WebhookError::InvalidPayload)?;
+    let Some(email_event) = event.as_email_event() else {
+        // 返回 200，避免 Postmark 不断重试我们不关心的事件
+        tracing::info!("Ignoring an unsupported Postmark event.");
+        return Ok(HttpResponse::Ok().finish());
+    };
+    tracing::Span::current()
+        .record("record_type", email_event.event_type)
+        .record(
+            "subscriber_email",
+            tracing::field::display(redact(email_event.email)),
+        );
+
+    let mut transaction = begin_transaction(&pool)
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    record_event(&mut transaction, &email_event)
+        .await
+        .context("Failed to record an email event.")?;
+    if email_event.suppress {
+        suppress_subscriber(&mut transaction, email_event.email)
+            .await
+            .context("Failed to suppress a subscriber.")?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to record an email event.")?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Postmark 会重试失败的回调，同一事件重复推送时只记录一次
+#[tracing::instrument(skip_all)]
+async fn record_event(
+    transaction: &mut Transaction<'_, Postgres>,
+    event: &EmailEvent<'_>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO email_events (
+            id,
+            subscriber_id,
+            email,
+            event_type,
+            detail,
+            message_id,
+            occurred_at,
+            received_at
+        )
+        SELECT
+            $1,
+            (SELECT id FROM subscriptions WHERE lower(email) = lower($2) LIMIT 1),
+            $2, $3, $4, $5, $6, now()
+        ON CONFLICT (message_id, event_type, email) DO NOTHING
+        "#,
+        Uuid::new_v4(),
+        event.email,
+        event.event_type,
+        event.detail,
+        event.message_id,
+        event.occurred_at,
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+/// 被抑制的地址不再收到任何邮件，也不能通过退订或重新订阅解除；
+/// Postmark 回调中的地址大小写可能与订阅时填写的不同，比较时忽略大小写
+#[tracing::instrument(skip(transaction, email), fields(subscriber_email = %redact(email)))]
+async fn suppress_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &str,
+) -> Result<(), sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET status = 'suppressed', suppressed_at = now()
+        WHERE lower(email) = lower($1) AND status != 'suppressed'
+        "#,
+        email,
+    )
+    .execute(&mut **transaction)
+    .await?;
+    if result.rows_affected() > 0 {
+        tracing::warn!("Suppressed a subscriber after a hard bounce or spam complaint.");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::routes::webhooks::PostmarkEvent;
+
+    #[test]
+    fn hard_bounces_are_suppressed_and_soft_bounces_are_not() {
+        let event = |bounce_type: &str| -> PostmarkEvent {
+            serde_json::from_value(serde_json::json!({
+                "RecordType": "Bounce",
+                "Type": bounce_type,
+                "MessageID": "883953f4-6105-42a2-a16a-77a8eac79483",
+                "Email": "zaphod@example.com",
+                "BouncedAt": "2019-11-05T16:33:54.9070259Z",
+            }))
+            .unwrap()
+        };
+
+        assert!(event("HardBounce").as_email_event().unwrap().suppress);
+        assert!(event("BadEmailAddress").as_email_event().unwrap().suppress);
+        assert!(!event("SoftBounce").as_email_event().unwrap().suppress);
+        assert!(!event("AutoResponder").as_email_event().unwrap().suppress);
+    }
+
+    #[test]
+    fn unsupported_record_types_are_ignored() {
+        let event: PostmarkEvent = serde_json::from_value(serde_json::json!({
+            "RecordType": "SubscriptionChange",
+            "MessageID": "883953f4-6105-42a2-a16a-77a8eac79483",
+        }))
+        .unwrap();
+
+        assert!(event.as_email_event().is_none());
+    }
+}
@@ -0,0 +1,78 @@
+mod memory;
+mod postgres;
+
+pub use memory::InMemorySessionStore;
+pub use postgres::PostgresSessionStore;
+
+use std::collections::HashMap;
+
+use actix_session::storage::{LoadError, SaveError, SessionKey, SessionStore, UpdateError};
+use actix_web::cookie::time::Duration;
+use sqlx::PgPool;
+
+use crate::configuration::SessionStoreKind;
+
+/// 根据配置选择的 session 存储后端
+#[derive(Clone)]
+pub enum SessionBackend {
+    InMemory(InMemorySessionStore),
+    Postgres(PostgresSessionStore),
+}
+
+impl SessionBackend {
+    pub fn new(kind: SessionStoreKind, pool: PgPool) -> Self {
+        match kind {
+            SessionStoreKind::Memory => Self::InMemory(InMemorySessionStore::default()),
+            SessionStoreKind::Postgres => Self::Postgres(PostgresSessionStore::new(pool)),
+        }
+    }
+}
+
+impl SessionStore for SessionBackend {
+    async fn load(
+        &self,
+        session_key: &SessionKey,
+    ) -> Result<Option<HashMap<String, String>>, LoadError> {
+        match self {
+            Self::InMemory(store) => store.load(session_key).await,
+            Self::Postgres(store) => store.load(session_key).await,
+        }
+    }
+
+    async fn save(
+        &self,
+        session_state: HashMap<String, String>,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        match self {
+            Self::InMemory(store) => store.save(session_state, ttl).await,
+            Self::Postgres(store) => store.save(session_state, ttl).await,
+        }
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: HashMap<String, String>,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        match self {
+            Self::InMemory(store) => store.update(session_key, session_state, ttl).await,
+            Self::Postgres(store) => store.update(session_key, session_state, ttl).await,
+        }
+    }
+
+    async fn update_ttl(&self, session_key: &SessionKey, ttl: &Duration) -> anyhow::Result<()> {
+        match self {
+            Self::InMemory(store) => store.update_ttl(session_key, ttl).await,
+            Self::Postgres(store) => store.update_ttl(session_key, ttl).await,
+        }
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> anyhow::Result<()> {
+        match self {
+            Self::InMemory(store) => store.delete(session_key).await,
+            Self::Postgres(store) => store.delete(session_key).await,
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use actix_session::storage::{
+    generate_session_key, LoadError, SaveError, SessionKey, SessionStore, UpdateError,
+};
+use actix_web::cookie::time::{Duration, OffsetDateTime};
+
+type SessionState = HashMap<String, String>;
+
+/// 进程内的 session 存储，所有 worker 共享同一份数据，仅用于测试和本地开发
+#[derive(Clone, Default)]
+pub struct InMemorySessionStore {
+    sessions: Arc<RwLock<HashMap<String, (SessionState, OffsetDateTime)>>>,
+}
+
+impl SessionStore for InMemorySessionStore {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        let sessions = self.sessions.read().map_err(|_| {
+            LoadError::Other(anyhow::anyhow!("The session store lock is poisoned."))
+        })?;
+        Ok(sessions
+            .get(session_key.as_ref())
+            .filter(|(_, expires_at)| *expires_at > OffsetDateTime::now_utc())
+            .map(|(state, _)| state.clone()))
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        let session_key = generate_session_key();
+        let now = OffsetDateTime::now_utc();
+        let mut sessions = self.sessions.write().map_err(|_| {
+            SaveError::Other(anyhow::anyhow!("The session store lock is poisoned."))
+        })?;
+        // 新 session 只在登录时创建，顺便清理已过期的记录，避免内存无限增长
+        sessions.retain(|_, (_, expires_at)| *expires_at > now);
+        sessions.insert(session_key.as_ref().to_owned(), (session_state, now + *ttl));
+        Ok(session_key)
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        self.sessions
+            .write()
+            .map_err(|_| {
+                UpdateError::Other(anyhow::anyhow!("The session store lock is poisoned."))
+            })?
+            .insert(
+                session_key.as_ref().to_owned(),
+                (session_state, OffsetDateTime::now_utc() + *ttl),
+            );
+        Ok(session_key)
+    }
+
+    async fn update_ttl(&self, session_key: &SessionKey, ttl: &Duration) -> anyhow::Result<()> {
+        let mut sessions = self
+            .sessions
+            .write()
+            .map_err(|_| anyhow::anyhow!("The session store lock is poisoned."))?;
+        if let Some((_, expires_at)) = sessions.get_mut(session_key.as_ref()) {
+            *expires_at = OffsetDateTime::now_utc() + *ttl;
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> anyhow::Result<()> {
+        self.sessions
+            .write()
+            .map_err(|_| anyhow::anyhow!("The session store lock is poisoned."))?
+            .remove(session_key.as_ref());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use actix_session::storage::SessionStore;
+    use actix_web::cookie::time::Duration;
+    use claim::assert_none;
+
+    use crate::session_store::InMemorySessionStore;
+
+    fn state() -> HashMap<String, String> {
+        HashMap::from([("user_id".to_string(), "\"42\"".to_string())])
+    }
+
+    #[tokio::test]
+    async fn a_saved_session_can_be_loaded() {
+        let store = InMemorySessionStore::default();
+
+        let session_key = store.save(state(), &Duration::minutes(5)).await.unwrap();
+
+        let loaded = store.load(&session_key).await.unwrap();
+        assert_eq!(loaded, Some(state()));
+    }
+
+    #[tokio::test]
+    async fn an_expired_session_is_not_loaded() {
+        let store = InMemorySessionStore::default();
+
+        let session_key = store.save(state(), &Duration::seconds(-1)).await.unwrap();
+
+        assert_none!(store.load(&session_key).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn expired_sessions_are_purged_when_a_new_session_is_saved() {
+        let store = InMemorySessionStore::default();
+        store.save(state(), &Duration::seconds(-1)).await.unwrap();
+
+        let session_key = store.save(state(), &Duration::minutes(5)).await.unwrap();
+
+        let sessions = store.sessions.read().unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert!(sessions.contains_key(session_key.as_ref()));
+    }
+
+    #[tokio::test]
+    async fn a_deleted_session_is_not_loaded() {
+        let store = InMemorySessionStore::default();
+        let session_key = store.save(state(), &Duration::minutes(5)).await.unwrap();
+
+        store.delete(&session_key).await.unwrap();
+
+        assert_none!(store.load(&session_key).await.unwrap());
+    }
+}
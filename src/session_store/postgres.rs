@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+
+use actix_session::storage::{
+    generate_session_key, LoadError, SaveError, SessionKey, SessionStore, UpdateError,
+};
+use actix_web::cookie::time::Duration;
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::PgPool;
+
+type SessionState = HashMap<String, String>;
+
+/// 基于 `sessions` 表的 session 存储，适用于多实例部署
+#[derive(Clone)]
+pub struct PostgresSessionStore {
+    pool: PgPool,
+}
+
+impl PostgresSessionStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+fn expires_at(ttl: &Duration) -> chrono::DateTime<Utc> {
+    Utc::now() + chrono::Duration::seconds(ttl.whole_seconds())
+}
+
+impl SessionStore for PostgresSessionStore {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        let row = sqlx::query!(
+            r#"
+            SELECT state
+            FROM sessions
+            WHERE session_key = $1 AND expires_at > now()
+            "#,
+            session_key.as_ref(),
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to load the session state.")
+        .map_err(LoadError::Other)?;
+        row.map(|r| serde_json::from_value(r.state))
+            .transpose()
+            .context("Failed to deserialize the session state.")
+            .map_err(LoadError::Deserialization)
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        let state = serde_json::to_value(session_state)
+            .context("Failed to serialize the session state.")
+            .map_err(SaveError::Serialization)?;
+        // 新 session 只在登录时创建，顺便清理已过期的记录，避免表无限增长
+        sqlx::query!(r#"DELETE FROM sessions WHERE expires_at <= now()"#)
+            .execute(&self.pool)
+            .await
+            .context("Failed to purge expired sessions.")
+            .map_err(SaveError::Other)?;
+        let session_key = generate_session_key();
+        sqlx::query!(
+            r#"
+            INSERT INTO sessions (session_key, state, expires_at)
+            VALUES ($1, $2, $3)
+            "#,
+            session_key.as_ref(),
+            state,
+            expires_at(ttl),
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to save the session state.")
+        .map_err(SaveError::Other)?;
+        Ok(session_key)
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        let state = serde_json::to_value(session_state)
+            .context("Failed to serialize the session state.")
+            .map_err(UpdateError::Serialization)?;
+        sqlx::query!(
+            r#"
+            INSERT INTO sessions (session_key, state, expires_at)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (session_key) DO UPDATE
+            SET state = EXCLUDED.state, expires_at = EXCLUDED.expires_at
+            "#,
+            session_key.as_ref(),
+            state,
+            expires_at(ttl),
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to update the session state.")
+        .map_err(UpdateError::Other)?;
+        Ok(session_key)
+    }
+
+    async fn update_ttl(&self, session_key: &SessionKey, ttl: &Duration) -> anyhow::Result<()> {
+        sqlx::query!(
+            r#"UPDATE sessions SET expires_at = $2 WHERE session_key = $1"#,
+            session_key.as_ref(),
+            expires_at(ttl),
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to update the session expiry.")?;
+        Ok(())
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> anyhow::Result<()> {
+        sqlx::query!(
+            r#"DELETE FROM sessions WHERE session_key = $1"#,
+            session_key.as_ref(),
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to delete the session.")?;
+        Ok(())
+    }
+}
@@ -1,35 +1,55 @@
-use crate::configuration::{DatabaseSettings, Settings};
+use crate::authentication::middleware::reject_anonymous_users;
+use crate::authentication::provision_admin;
+use crate::configuration::{DatabaseSettings, HealthSettings, Settings};
+use crate::domain::tracking_token::TrackingLinks;
+use crate::domain::unsubscribe_token::UnsubscribeLinks;
 use crate::email_client::EmailClient;
-use crate::routes::{health_check, subscribe};
+use crate::metrics::{metrics, record_http_metrics};
+use crate::request_id::propagate_request_id;
+use crate::routes::{
+    admin_dashboard, change_log_level, change_password, change_password_form, confirm,
+    health_check, health_live, health_ready, list_dead_letters, list_engagement, log_level,
+    log_out, login, login_form, postmark_webhook, publish_newsletter, requeue_dead_letters,
+    subscribe, track, tracking_opt_out, tracking_opt_out_form, unsubscribe, unsubscribe_form,
+    WebhookCredentials,
+};
+use crate::session_store::SessionBackend;
+use crate::telemetry::{LogFilterHandle, RequestRootSpanBuilder};
+use actix_session::SessionMiddleware;
+use actix_web::cookie::Key;
 use actix_web::dev::Server;
+use actix_web::middleware::from_fn;
 use actix_web::web::Data;
 use actix_web::{web, App, HttpServer};
-use secrecy::ExposeSecret;
-use sqlx::postgres::PgPoolOptions;
+use actix_web_flash_messages::storage::CookieMessageStore;
+use actix_web_flash_messages::FlashMessagesFramework;
+use anyhow::Context;
+use secrecy::{ExposeSecret, SecretBox};
 use sqlx::PgPool;
 use std::io::Error;
 use std::net::TcpListener;
-use std::time::Duration;
 use tracing_actix_web::TracingLogger;
 
 pub struct Application {
     pub port: u16,
     pub server: Server,
+    pub metrics_port: u16,
+    pub metrics_server: Server,
 }
 
 impl Application {
-    pub async fn build(configuration: &Settings) -> Result<Self, Error> {
+    pub async fn build(
+        configuration: &Settings,
+        log_filter: LogFilterHandle,
+    ) -> Result<Self, anyhow::Error> {
         let connection_pool = get_connection_pool(&configuration.database);
 
-        let sender_email = configuration
-            .email_client
-            .sender()
-            .expect("Invalid sender email address.");
-
-        let timeout = configuration.email_client.timeout();
-        let base_url = configuration.email_client.base_url;
-        let authorization_token = configuration.email_client.authorization_token;
-        let email_client = EmailClient::new(base_url, sender_email, authorization_token, timeout);
+        let email_client = configuration.email_client.client()?;
+        if let Some(admin) = &configuration.admin {
+            provision_admin(admin, &connection_pool)
+                .await
+                .context("Failed to provision the admin account.")?;
+        }
 
         let address = format!(
             "{}:{}",
@@ -37,69 +57,170 @@ impl Application {
         );
         let listener = TcpListener::bind(address)?;
         let port = listener.local_addr().unwrap().port();
-        let server = run(listener, connection_pool, email_client)?;
-        Ok(Self { port, server })
+        let metrics_listener = TcpListener::bind(format!(
+            "{}:{}",
+            configuration.metrics.host, configuration.metrics.port
+        ))?;
+        let metrics_port = metrics_listener.local_addr().unwrap().port();
+        let metrics_server = run_metrics(metrics_listener, connection_pool.clone())?;
+        let session_store = SessionBackend::new(
+            configuration.application.session_store,
+            connection_pool.clone(),
+        );
+        let server = run(
+            listener,
+            connection_pool,
+            email_client,
+            configuration.application.base_url.clone(),
+            HmacSecret(SecretBox::new(Box::new(
+                configuration
+                    .application
+                    .hmac_secret
+                    .expose_secret()
+                    .clone(),
+            ))),
+            session_store,
+            configuration.health.clone(),
+            configuration.webhooks.credentials(),
+            log_filter,
+        )?;
+        Ok(Self {
+            port,
+            server,
+            metrics_port,
+            metrics_server,
+        })
     }
 
     pub fn port(&self) -> u16 {
         self.port
     }
 
-    pub async fn run_until_stopped(self) -> Result<(), Error> {
-        self.server.await
+    pub fn metrics_port(&self) -> u16 {
+        self.metrics_port
     }
-}
-
-pub async fn build(configuration: Settings) -> Result<Server, Error> {
-    let connection_pool = get_connection_pool(&configuration.database);
 
-    let sender_email = configuration
-        .email_client
-        .sender()
-        .expect("Invalid sender email address.");
-    let timeout = configuration.email_client.timeout();
-    let email_client = EmailClient::new(
-        configuration.email_client.base_url,
-        sender_email,
-        configuration.email_client.authorization_token,
-        timeout,
-    );
-
-    let address = format!(
-        "{}:{}",
-        configuration.application.host, configuration.application.port
-    );
-    let listener = TcpListener::bind(address)?;
-    run(listener, connection_pool, email_client)
+    /// 应用与指标接口任意一个出错时返回
+    pub async fn run_until_stopped(self) -> Result<(), Error> {
+        tokio::try_join!(self.server, self.metrics_server)?;
+        Ok(())
+    }
 }
 
 pub fn get_connection_pool(configuration: &DatabaseSettings) -> PgPool {
-    PgPoolOptions::new()
-        .acquire_timeout(Duration::from_secs(2))
-        .connect_lazy_with(
-            configuration
-                .connection_string()
-                .expose_secret()
-                .parse()
-                .unwrap(),
-        )
+    configuration
+        .pool
+        .options()
+        .connect_lazy_with(configuration.with_db())
 }
 
+/// 应用对外访问的基础地址，用于生成确认链接等
+pub struct ApplicationBaseUrl(pub String);
+
+/// 用于签名 session 与 flash cookie 的密钥
+pub struct HmacSecret(pub SecretBox<String>);
+
+#[allow(clippy::too_many_arguments)]
 pub fn run(
     listener: TcpListener,
     db_pool: PgPool,
     email_client: EmailClient,
+    base_url: String,
+    hmac_secret: HmacSecret,
+    session_store: SessionBackend,
+    health: HealthSettings,
+    webhook_credentials: WebhookCredentials,
+    log_filter: LogFilterHandle,
 ) -> Result<Server, Error> {
     let db_pool = Data::new(db_pool);
+    let log_filter = Data::new(log_filter);
+    let webhook_credentials = Data::new(webhook_credentials);
     let email_client = Data::new(email_client);
+    let health = Data::new(health);
+    let unsubscribe_links = Data::new(UnsubscribeLinks::new(
+        base_url.clone(),
+        SecretBox::new(Box::new(hmac_secret.0.expose_secret().clone())),
+    ));
+    let tracking_links = Data::new(TrackingLinks::new(
+        base_url.clone(),
+        SecretBox::new(Box::new(hmac_secret.0.expose_secret().clone())),
+    ));
+    let base_url = Data::new(ApplicationBaseUrl(base_url));
+    let secret_key = Key::from(hmac_secret.0.expose_secret().as_bytes());
+    let message_store = CookieMessageStore::builder(secret_key.clone()).build();
+    let message_framework = FlashMessagesFramework::builder(message_store).build();
     let server = HttpServer::new(move || {
         App::new()
-            .wrap(TracingLogger::default())
+            .wrap(message_framework.clone())
+            .wrap(SessionMiddleware::new(
+                session_store.clone(),
+                secret_key.clone(),
+            ))
+            .wrap(TracingLogger::<RequestRootSpanBuilder>::new())
+            .wrap(from_fn(record_http_metrics))
+            .wrap(from_fn(propagate_request_id))
             .route("/health_check", web::get().to(health_check))
+            .route("/health/live", web::get().to(health_live))
+            .route("/health/ready", web::get().to(health_ready))
+            .route("/login", web::get().to(login_form))
+            .route("/login", web::post().to(login))
+            .service(
+                web::scope("/admin")
+                    .wrap(from_fn(reject_anonymous_users))
+                    .route("/dashboard", web::get().to(admin_dashboard))
+                    .route("/password", web::get().to(change_password_form))
+                    .route("/password", web::post().to(change_password))
+                    .route("/logout", web::post().to(log_out))
+                    .route("/dead_letters", web::get().to(list_dead_letters))
+                    .route("/engagement", web::get().to(list_engagement))
+                    .route("/log_level", web::get().to(log_level))
+                    .route("/log_level", web::post().to(change_log_level))
+                    .route(
+                        "/dead_letters/requeue",
+                        web::post().to(requeue_dead_letters),
+                    ),
+            )
             .route("/subscriptions", web::post().to(subscribe))
+            .route("/subscriptions/confirm", web::get().to(confirm))
+            .route(
+                "/subscriptions/unsubscribe",
+                web::get().to(unsubscribe_form),
+            )
+            .route("/subscriptions/unsubscribe", web::post().to(unsubscribe))
+            .route(
+                "/subscriptions/tracking_opt_out",
+                web::get().to(tracking_opt_out_form),
+            )
+            .route(
+                "/subscriptions/tracking_opt_out",
+                web::post().to(tracking_opt_out),
+            )
+            .route("/t/{token}", web::get().to(track))
+            .route("/newsletters", web::post().to(publish_newsletter))
+            .route("/webhooks/postmark", web::post().to(postmark_webhook))
             .app_data(db_pool.clone())
             .app_data(email_client.clone())
+            .app_data(base_url.clone())
+            .app_data(unsubscribe_links.clone())
+            .app_data(tracking_links.clone())
+            .app_data(health.clone())
+            .app_data(webhook_credentials.clone())
+            .app_data(log_filter.clone())
+    })
+    .listen(listener)?
+    .run();
+    Ok(server)
+}
+
+/// 只提供 `/metrics` 的服务，与应用分开监听，便于只对内网的 Prometheus 开放
+pub fn run_metrics(listener: TcpListener, db_pool: PgPool) -> Result<Server, Error> {
+    let db_pool = Data::new(db_pool);
+    let server = HttpServer::new(move || {
+        App::new()
+            .route("/metrics", web::get().to(metrics))
+            .app_data(db_pool.clone())
     })
+    .workers(1)
     .listen(listener)?
     .run();
     Ok(server)
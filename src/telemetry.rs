@@ -1,30 +1,600 @@
+use crate::configuration::{LogFormat, LogRedaction, TelemetrySettings};
+use crate::request_id::RequestId;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::HeaderMap;
+use actix_web::HttpMessage;
+use anyhow::Context;
+use hmac::{Hmac, Mac};
+use opentelemetry::propagation::Extractor;
+use opentelemetry::propagation::TextMapPropagator;
+use opentelemetry::trace::TraceContextExt;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::TracerProvider;
+use opentelemetry_sdk::{runtime, Resource};
+use rand::Rng;
+use secrecy::{ExposeSecret, SecretBox};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use tokio::task::JoinHandle;
 use tracing::subscriber::set_global_default;
-use tracing::Subscriber;
+use tracing::{Span, Subscriber};
+use tracing_actix_web::{DefaultRootSpanBuilder, RootSpanBuilder};
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_log::LogTracer;
-use tracing_subscriber::{fmt::MakeWriter, layer::SubscriberExt, EnvFilter, Registry};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::fmt::{self, MakeWriter};
+use tracing_subscriber::layer::{Layer, SubscriberExt};
+use tracing_subscriber::{reload, EnvFilter, Registry};
 
+/// 创建 OpenTelemetry 的 `TracerProvider`：配置了 `otlp_endpoint` 时通过 OTLP/HTTP 批量导出 span；
+/// 否则只生成 trace 上下文，不导出，请求仍可以把上游的 `traceparent` 传递给下游。
+/// 导出在独立线程的单线程运行时中进行，`actix_web::main` 的运行时上调用 `shutdown` 不会卡住
+pub fn get_tracer_provider(
+    name: &str,
+    settings: &TelemetrySettings,
+) -> Result<TracerProvider, anyhow::Error> {
+    let mut builder = TracerProvider::builder().with_resource(Resource::new([KeyValue::new(
+        "service.name",
+        name.to_owned(),
+    )]));
+    if let Some(endpoint) = &settings.otlp_endpoint {
+        let exporter = SpanExporter::builder()
+            .with_http()
+            .with_endpoint(endpoint)
+            .with_timeout(settings.otlp_timeout())
+            .build()?;
+        builder = builder.with_batch_exporter(exporter, runtime::TokioCurrentThread);
+    }
+    Ok(builder.build())
+}
+
+/// 按 `format` 输出日志；`ansi` 控制 `compact` 与 `pretty` 格式是否使用终端颜色，写入文件时应关闭。
+/// 返回的 `LogFilterHandle` 可以在运行时替换过滤规则
 pub fn get_subscriber<Sink>(
     name: String,
     env_filter: String,
+    format: LogFormat,
+    ansi: bool,
     sink: Sink,
-) -> impl Subscriber + Send + Sync
+    tracer_provider: &TracerProvider,
+) -> (impl Subscriber + Send + Sync, LogFilterHandle)
 where
     Sink: for<'a> MakeWriter<'a> + Send + Sync + 'static,
 {
     let env_filter =
         EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(env_filter));
-    let formatting_layer = BunyanFormattingLayer::new(name, sink);
+    let (filter_layer, filter_handle) = reload::Layer::new(env_filter);
+    let otel_layer =
+        tracing_opentelemetry::layer().with_tracer(tracer_provider.tracer(name.clone()));
+    let (bunyan_layer, compact_layer, pretty_layer) = match format {
+        LogFormat::Bunyan => (
+            Some(JsonStorageLayer.and_then(BunyanFormattingLayer::new(name, sink))),
+            None,
+            None,
+        ),
+        LogFormat::Compact => (
+            None,
+            Some(fmt::layer().compact().with_ansi(ansi).with_writer(sink)),
+            None,
+        ),
+        LogFormat::Pretty => (
+            None,
+            None,
+            Some(fmt::layer().pretty().with_ansi(ansi).with_writer(sink)),
+        ),
+    };
 
     let subscriber = Registry::default()
-        .with(env_filter)
-        .with(JsonStorageLayer)
-        .with(formatting_layer);
+        .with(filter_layer)
+        .with(otel_layer)
+        .with(bunyan_layer)
+        .with(compact_layer)
+        .with(pretty_layer);
+    (subscriber, LogFilterHandle(filter_handle))
+}
+
+/// 运行时查看与替换日志过滤规则，例如临时打开 `sqlx=debug` 而不必重启
+#[derive(Clone)]
+pub struct LogFilterHandle(reload::Handle<EnvFilter, Registry>);
+
+impl LogFilterHandle {
+    /// 当前生效的过滤规则
+    pub fn current(&self) -> Result<String, anyhow::Error> {
+        let filter = self
+            .0
+            .with_current(|filter| filter.to_string())
+            .context("Failed to read the current log filter.")?;
+        Ok(filter)
+    }
 
-    subscriber
+    /// 替换过滤规则；规则不合法时当前规则保持不变
+    pub fn set(&self, filter: &str) -> Result<(), LogFilterError> {
+        let filter = EnvFilter::try_new(filter).map_err(LogFilterError::InvalidFilter)?;
+        self.0
+            .reload(filter)
+            .context("Failed to reload the log filter.")?;
+        Ok(())
+    }
 }
 
+#[derive(thiserror::Error, Debug)]
+pub enum LogFilterError {
+    #[error(transparent)]
+    InvalidFilter(tracing_subscriber::filter::ParseError),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+/// 安装全局的 subscriber，并使用 W3C Trace Context（`traceparent`）在服务之间传递 trace
 pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
     LogTracer::init().expect("Failed to set logger");
     set_global_default(subscriber).expect("Failed to set subscriber");
+    opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+}
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// 与令牌签名共用同一个密钥，加上前缀避免日志中的哈希值可以被当作签名使用
+const REDACTION_DOMAIN: &[u8] = b"log-redaction:";
+
+/// 进程内统一的个人信息处理方式与哈希密钥，启动时设置一次
+static REDACTION: OnceLock<RedactionSettings> = OnceLock::new();
+
+struct RedactionSettings {
+    redaction: LogRedaction,
+    /// 已写入 `REDACTION_DOMAIN` 的 HMAC 状态，每次计算时复制一份
+    key: HmacSha256,
+}
+
+impl RedactionSettings {
+    fn new(redaction: LogRedaction, key: &[u8]) -> Self {
+        let mut key = HmacSha256::new_from_slice(key).expect("HMAC can take a key of any size");
+        key.update(REDACTION_DOMAIN);
+        Self { redaction, key }
+    }
+}
+
+/// 出现在请求路径或查询参数中时需要脱敏的参数名
+const SENSITIVE_PARAMETERS: [&str; 2] = ["token", "subscription_token"];
+
+/// 设置日志中个人信息的处理方式；`Hash` 模式用 `key` 计算 HMAC，不知道密钥就无法通过字典反推原值。
+/// 未设置时按 `LogRedaction::Hash` 处理，密钥随机生成，哈希值只在当前进程内一致
+pub fn set_redaction(redaction: LogRedaction, key: &SecretBox<String>) {
+    if REDACTION
+        .set(RedactionSettings::new(
+            redaction,
+            key.expose_secret().as_bytes(),
+        ))
+        .is_err()
+    {
+        panic!("Failed to set log redaction");
+    }
+}
+
+/// 邮箱、姓名、用户名、令牌等个人信息一律通过 `redact` 写入日志字段，例如
+/// `subscriber_email = %redact(&form.email)`，不要直接记录原值
+pub fn redact(value: &str) -> Redacted<'_> {
+    let settings = REDACTION.get_or_init(|| {
+        RedactionSettings::new(LogRedaction::Hash, &rand::thread_rng().gen::<[u8; 32]>())
+    });
+    Redacted { value, settings }
+}
+
+/// 按配置输出哈希值、掩码或原值的个人信息
+pub struct Redacted<'a> {
+    value: &'a str,
+    settings: &'a RedactionSettings,
+}
+
+impl std::fmt::Display for Redacted<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.settings.redaction {
+            // 截取前 16 位十六进制，足以区分不同的值
+            LogRedaction::Hash => {
+                let mut mac = self.settings.key.clone();
+                mac.update(self.value.as_bytes());
+                let digest = format!("{:x}", mac.finalize().into_bytes());
+                write!(f, "hmac-sha256:{}", &digest[..16])
+            }
+            LogRedaction::Mask => f.write_str("[redacted]"),
+            LogRedaction::Off => f.write_str(self.value),
+        }
+    }
+}
+
+/// 脱敏一段外部文本（例如邮件服务商返回的错误信息）中出现的邮箱地址，其余内容保持不变
+pub fn redact_emails(text: &str) -> String {
+    text.split_inclusive(char::is_whitespace)
+        .map(|word| {
+            // 去掉地址前后的引号、括号与句末标点
+            let candidate = word.trim_matches(|c: char| !c.is_alphanumeric());
+            if candidate.contains('@') {
+                word.replacen(candidate, &redact(candidate).to_string(), 1)
+            } else {
+                word.to_owned()
+            }
+        })
+        .collect()
+}
+
+/// 写入日志的请求地址：路由参数与查询参数中的令牌（见 `SENSITIVE_PARAMETERS`）经过脱敏
+fn redacted_target(path: &str, pattern: Option<&str>, query: &str) -> String {
+    let is_sensitive = |name: &str| SENSITIVE_PARAMETERS.contains(&name);
+    let path = match pattern {
+        Some(pattern) => pattern
+            .split('/')
+            .zip(path.split('/'))
+            .map(|(pattern, segment)| {
+                match pattern.strip_prefix('{').and_then(|p| p.strip_suffix('}')) {
+                    Some(name) if is_sensitive(name) => redact(segment).to_string(),
+                    _ => segment.to_owned(),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("/"),
+        None => path.to_owned(),
+    };
+    let query = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((name, value)) if is_sensitive(name) => format!("{}={}", name, redact(value)),
+            _ => pair.to_owned(),
+        })
+        .collect::<Vec<_>>()
+        .join("&");
+    if query.is_empty() {
+        path
+    } else {
+        format!("{}?{}", path, query)
+    }
+}
+
+/// 当前 span 的 trace 上下文对应的请求头，附加到出站 HTTP 请求上，让下游服务延续同一个 trace
+pub fn trace_context_headers() -> HashMap<String, String> {
+    let context = tracing::Span::current().context();
+    let mut headers = HashMap::new();
+    TraceContextPropagator::new().inject_context(&context, &mut headers);
+    headers
+}
+
+/// 每个请求的根 span：与 `tracing-actix-web` 默认的字段一致，但 `request_id` 使用
+/// `propagate_request_id` 确定的请求 id，`http.target` 中的令牌经过脱敏，并延续请求头 `traceparent` 中的 trace
+pub struct RequestRootSpanBuilder;
+
+impl RootSpanBuilder for RequestRootSpanBuilder {
+    fn on_request_start(request: &ServiceRequest) -> Span {
+        let request_id = request
+            .extensions()
+            .get::<RequestId>()
+            .cloned()
+            .unwrap_or_else(RequestId::generate);
+        let method = request.method().as_str();
+        let pattern = request.match_pattern();
+        let route = pattern.as_deref().unwrap_or("default");
+        let user_agent = request
+            .headers()
+            .get("User-Agent")
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or("");
+        let connection_info = request.connection_info();
+        let target = redacted_target(request.path(), pattern.as_deref(), request.query_string());
+        let span = tracing::info_span!(
+            "HTTP request",
+            http.method = %method,
+            http.route = %route,
+            http.scheme = %connection_info.scheme(),
+            http.host = %connection_info.host(),
+            http.client_ip = %connection_info.realip_remote_addr().unwrap_or(""),
+            http.user_agent = %user_agent,
+            http.target = %target,
+            http.status_code = tracing::field::Empty,
+            otel.name = %format!("{} {}", method, route),
+            otel.kind = "server",
+            otel.status_code = tracing::field::Empty,
+            trace_id = tracing::field::Empty,
+            request_id = %request_id,
+            exception.message = tracing::field::Empty,
+            exception.details = tracing::field::Empty,
+        );
+        let parent = opentelemetry::global::get_text_map_propagator(|propagator| {
+            propagator.extract(&HeaderExtractor(request.headers()))
+        });
+        span.set_parent(parent);
+        let trace_id = span.context().span().span_context().trace_id();
+        span.record("trace_id", tracing::field::display(trace_id));
+        span
+    }
+
+    fn on_request_end<B: MessageBody>(
+        span: Span,
+        outcome: &Result<ServiceResponse<B>, actix_web::Error>,
+    ) {
+        DefaultRootSpanBuilder::on_request_end(span, outcome);
+    }
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| v.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|k| k.as_str()).collect()
+    }
+}
+
+/// 在阻塞线程池中执行 CPU 密集型任务，并沿用当前的 tracing span
+pub fn spawn_blocking_with_tracing<F, R>(f: F) -> JoinHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let current_span = tracing::Span::current();
+    tokio::task::spawn_blocking(move || current_span.in_scope(f))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::configuration::{LogFormat, LogRedaction, TelemetrySettings};
+    use crate::telemetry::{
+        get_subscriber, get_tracer_provider, redact, redact_emails, redacted_target,
+        trace_context_headers, Redacted, RedactionSettings,
+    };
+    use claim::assert_err;
+    use opentelemetry::propagation::TextMapPropagator;
+    use opentelemetry_sdk::propagation::TraceContextPropagator;
+    use opentelemetry_sdk::trace::TracerProvider;
+    use sha2::{Digest, Sha256};
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+
+    fn settings(otlp_endpoint: Option<String>) -> TelemetrySettings {
+        TelemetrySettings {
+            otlp_endpoint,
+            otlp_timeout_milliseconds: 1000,
+        }
+    }
+
+    /// 在 `traceparent` 为 `TRACE_ID` 的远端 span 之下执行 `f`
+    fn in_remote_trace<R>(f: impl FnOnce() -> R) -> R {
+        let carrier = HashMap::from([(
+            "traceparent".to_string(),
+            format!("00-{}-00f067aa0ba902b7-01", TRACE_ID),
+        )]);
+        let parent = TraceContextPropagator::new().extract(&carrier);
+        let span = tracing::info_span!("Adding a new subscriber");
+        span.set_parent(parent);
+        span.in_scope(f)
+    }
+
+    #[tokio::test]
+    async fn outgoing_headers_continue_the_incoming_trace() {
+        let tracer_provider = get_tracer_provider("test", &settings(None)).unwrap();
+        let (subscriber, _) = get_subscriber(
+            "test".into(),
+            "info".into(),
+            LogFormat::Bunyan,
+            false,
+            std::io::sink,
+            &tracer_provider,
+        );
+
+        let headers = tracing::subscriber::with_default(subscriber, || {
+            in_remote_trace(trace_context_headers)
+        });
+
+        let traceparent = &headers["traceparent"];
+        assert!(
+            traceparent.starts_with(&format!("00-{}-", TRACE_ID)),
+            "{}",
+            traceparent
+        );
+        assert!(!traceparent.contains("00f067aa0ba902b7"));
+    }
+
+    /// 与 `actix_web::main` 一样运行在单线程运行时上，`force_flush` 和 `shutdown` 不能卡住
+    #[tokio::test]
+    async fn spans_are_exported_to_the_otlp_collector() {
+        // 用 wiremock 代替 OTLP collector，只检查收到的 protobuf 中包含 trace id
+        let collector = MockServer::start().await;
+        Mock::given(path("/v1/traces"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&collector)
+            .await;
+        let tracer_provider = get_tracer_provider(
+            "actix-demo",
+            &settings(Some(format!("{}/v1/traces", collector.uri()))),
+        )
+        .unwrap();
+        let (subscriber, _) = get_subscriber(
+            "test".into(),
+            "info".into(),
+            LogFormat::Bunyan,
+            false,
+            std::io::sink,
+            &tracer_provider,
+        );
+
+        tracing::subscriber::with_default(subscriber, || in_remote_trace(|| ()));
+        tracer_provider.force_flush();
+        tracer_provider.shutdown().unwrap();
+
+        let requests = collector.received_requests().await.unwrap();
+        assert_eq!(requests.len(), 1);
+        let trace_id: Vec<u8> = (0..TRACE_ID.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&TRACE_ID[i..i + 2], 16).unwrap())
+            .collect();
+        let body = &requests[0].body;
+        assert!(body.windows(trace_id.len()).any(|w| w == trace_id));
+        assert!(body.windows(10).any(|w| w == b"actix-demo"));
+    }
+
+    /// 日志写入内存，便于检查输出的内容
+    #[derive(Clone, Default)]
+    struct Logs(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Logs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Logs {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    #[test]
+    fn compact_logs_are_plain_text() {
+        let logs = Logs::default();
+        let writer = logs.clone();
+        let (subscriber, _) = get_subscriber(
+            "test".into(),
+            "info".into(),
+            LogFormat::Compact,
+            false,
+            move || writer.clone(),
+            &TracerProvider::default(),
+        );
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(subscriber_count = 3, "Delivering the newsletter.")
+        });
+
+        let logs = logs.contents();
+        assert!(logs.contains("Delivering the newsletter."), "{}", logs);
+        assert!(logs.contains("subscriber_count=3"), "{}", logs);
+        assert!(serde_json::from_str::<serde_json::Value>(logs.trim()).is_err());
+        assert!(!logs.contains('\x1b'), "{}", logs);
+    }
+
+    #[test]
+    fn the_log_filter_can_be_replaced_at_runtime() {
+        let logs = Logs::default();
+        let writer = logs.clone();
+        let (subscriber, log_filter) = get_subscriber(
+            "test".into(),
+            "info".into(),
+            LogFormat::Bunyan,
+            false,
+            move || writer.clone(),
+            &TracerProvider::default(),
+        );
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::debug!("Hidden.");
+            assert_err!(log_filter.set("sqlx=loud"));
+            log_filter.set("debug").unwrap();
+            tracing::debug!("Visible.");
+            assert_eq!(log_filter.current().unwrap(), "debug");
+        });
+
+        let logs = logs.contents();
+        assert!(!logs.contains("Hidden."), "{}", logs);
+        assert!(logs.contains("Visible."), "{}", logs);
+    }
+
+    #[test]
+    fn redacted_values_follow_the_configured_mode() {
+        let email = "928647866@qq.com";
+        let redacted = |redaction, key: &str| {
+            Redacted {
+                value: email,
+                settings: &RedactionSettings::new(redaction, key.as_bytes()),
+            }
+            .to_string()
+        };
+
+        let hashed = redacted(LogRedaction::Hash, "key");
+        assert!(hashed.starts_with("hmac-sha256:"), "{}", hashed);
+        assert_eq!(hashed.len(), "hmac-sha256:".len() + 16);
+        assert_eq!(hashed, redacted(LogRedaction::Hash, "key"));
+        assert_ne!(hashed, redact("someone-else@qq.com").to_string());
+        assert_eq!(redacted(LogRedaction::Mask, "key"), "[redacted]");
+        assert_eq!(redacted(LogRedaction::Off, "key"), email);
+    }
+
+    #[test]
+    fn hashed_values_cannot_be_recomputed_without_the_key() {
+        let email = "928647866@qq.com";
+        let hashed = |key: &str| {
+            Redacted {
+                value: email,
+                settings: &RedactionSettings::new(LogRedaction::Hash, key.as_bytes()),
+            }
+            .to_string()
+        };
+
+        // 不带密钥的 SHA-256 可以用常见邮箱的字典反推，带密钥的 HMAC 不行
+        let unkeyed = format!("{:x}", Sha256::digest(email.as_bytes()));
+        assert!(!hashed("key").contains(&unkeyed[..16]));
+        assert_ne!(hashed("key"), hashed("another-key"));
+    }
+
+    #[test]
+    fn tokens_in_the_request_target_are_redacted() {
+        let token = "0b8c1a6e6b2c4d3c";
+        let redacted = redact(token).to_string();
+
+        assert_eq!(
+            redacted_target(
+                &format!("/t/{}", token),
+                Some("/t/{token}"),
+                "utm_source=newsletter"
+            ),
+            format!("/t/{}?utm_source=newsletter", redacted)
+        );
+        assert_eq!(
+            redacted_target(
+                "/subscriptions/confirm",
+                Some("/subscriptions/confirm"),
+                &format!("subscription_token={}", token)
+            ),
+            format!("/subscriptions/confirm?subscription_token={}", redacted)
+        );
+        assert_eq!(redacted_target("/health_check", None, ""), "/health_check");
+    }
+
+    #[test]
+    fn email_addresses_in_free_text_are_redacted() {
+        let email = "someone@example.com";
+        let message = format!(
+            "You tried to send to recipient(s) that have been marked as inactive. \
+             Found inactive addresses: {}. Inactive recipients are ones that have generated \
+             a hard bounce or a spam complaint.",
+            email
+        );
+
+        let redacted = redact_emails(&message);
+
+        assert!(!redacted.contains(email));
+        assert!(redacted.contains(&format!(
+            "Found inactive addresses: {}. Inactive",
+            redact(email)
+        )));
+        assert_eq!(
+            redact_emails("Rate limit exceeded."),
+            "Rate limit exceeded."
+        );
+    }
 }
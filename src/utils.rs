@@ -0,0 +1,83 @@
+use actix_web::http::header::LOCATION;
+use actix_web::http::StatusCode;
+use actix_web::HttpResponse;
+
+/// 将任意错误包装为 500，错误详情只记录在日志中
+pub fn e500<T>(e: T) -> actix_web::Error
+where
+    T: std::fmt::Debug + std::fmt::Display + 'static,
+{
+    actix_web::error::ErrorInternalServerError(e)
+}
+
+/// 303 重定向，浏览器会以 GET 方式访问目标地址，避免刷新时重复提交表单
+pub fn see_other(location: &str) -> HttpResponse {
+    HttpResponse::SeeOther()
+        .insert_header((LOCATION, location))
+        .finish()
+}
+
+/// 按 `source()` 逐层输出错误链，用于错误类型的 `Debug` 实现
+pub fn error_chain_fmt(
+    e: &impl std::error::Error,
+    f: &mut std::fmt::Formatter<'_>,
+) -> std::fmt::Result {
+    writeln!(f, "{}\n", e)?;
+    let mut current = e.source();
+    while let Some(cause) = current {
+        writeln!(f, "Caused by:\n\t{}", cause)?;
+        current = cause.source();
+    }
+    Ok(())
+}
+
+/// RFC 7807 `application/problem+json` 格式的错误响应体，`request_id` 字段由 `propagate_request_id` 统一加入
+#[derive(serde::Serialize, Debug)]
+pub struct ProblemDetails {
+    #[serde(rename = "type")]
+    pub problem_type: &'static str,
+    pub title: String,
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    #[serde(rename = "invalid-params", skip_serializing_if = "Vec::is_empty")]
+    pub invalid_params: Vec<InvalidParam>,
+}
+
+/// 校验失败的字段及其原因
+#[derive(serde::Serialize, Debug)]
+pub struct InvalidParam {
+    pub name: &'static str,
+    pub reason: String,
+}
+
+impl ProblemDetails {
+    pub fn new(status: StatusCode) -> Self {
+        Self {
+            problem_type: "about:blank",
+            title: status.canonical_reason().unwrap_or_default().to_owned(),
+            status: status.as_u16(),
+            detail: None,
+            invalid_params: Vec::new(),
+        }
+    }
+
+    pub fn detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    pub fn invalid_param(mut self, name: &'static str, reason: impl Into<String>) -> Self {
+        self.invalid_params.push(InvalidParam {
+            name,
+            reason: reason.into(),
+        });
+        self
+    }
+
+    pub fn to_response(&self) -> HttpResponse {
+        HttpResponse::build(StatusCode::from_u16(self.status).unwrap())
+            .content_type("application/problem+json")
+            .json(self)
+    }
+}
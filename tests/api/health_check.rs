@@ -8,7 +8,7 @@ async fn health_check_works() {
 
     // 执行
     let response = client
-        .get(&format!("{}/health_check", &app.address))
+        .get(format!("{}/health_check", &app.address))
         .send()
         .await
         .expect("Failed to execute request.");
@@ -17,3 +17,65 @@ async fn health_check_works() {
     assert!(response.status().is_success());
     assert_eq!(Some(0), response.content_length());
 }
+
+#[tokio::test]
+async fn liveness_probe_returns_up() {
+    // 准备
+    let app = spawn_app().await;
+
+    // 执行
+    let response = reqwest::get(format!("{}/health/live", &app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    // 断言
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "up");
+}
+
+#[tokio::test]
+async fn readiness_probe_reports_every_dependency() {
+    // 准备
+    let app = spawn_app().await;
+
+    // 执行
+    let response = reqwest::get(format!("{}/health/ready", &app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    // 断言
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "up");
+    assert_eq!(body["checks"]["database"]["status"], "up");
+    assert!(body["checks"]["database"]["latency_ms"].is_u64());
+    assert_eq!(body["checks"]["migrations"]["status"], "up");
+    assert_eq!(body["checks"]["migrations"]["pending_migrations"], 0);
+    assert!(body["checks"].get("email_provider").is_none());
+}
+
+#[tokio::test]
+async fn readiness_probe_fails_when_migrations_are_pending() {
+    // 准备
+    let app = spawn_app().await;
+    sqlx::query!(
+        "DELETE FROM _sqlx_migrations WHERE version = (SELECT max(version) FROM _sqlx_migrations)"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // 执行
+    let response = reqwest::get(format!("{}/health/ready", &app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    // 断言
+    assert_eq!(response.status().as_u16(), 503);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "down");
+    assert_eq!(body["checks"]["database"]["status"], "up");
+    assert_eq!(body["checks"]["migrations"]["status"], "down");
+    assert_eq!(body["checks"]["migrations"]["pending_migrations"], 1);
+}
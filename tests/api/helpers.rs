@@ -1,64 +1,443 @@
 use actix_demo::{
-    configuration::{get_configuration, DatabaseSettings},
+    configuration::{
+        get_configuration, DatabaseSettings, EmailTransportKind, LogFormat, SessionStoreKind,
+    },
+    domain::{tracking_token::TrackingLinks, unsubscribe_token::UnsubscribeLinks},
+    email_client::EmailClient,
+    issue_delivery_worker::{try_execute_task, ExecutionOutcome, RetryPolicy},
     startup::{get_connection_pool, Application},
-    telemetry::{get_subscriber, init_subscriber},
+    telemetry::{get_subscriber, init_subscriber, LogFilterHandle},
 };
+use argon2::password_hash::SaltString;
+use argon2::{Algorithm, Argon2, Params, PasswordHasher, Version};
 use once_cell::sync::Lazy;
+use opentelemetry_sdk::trace::TracerProvider;
+use secrecy::ExposeSecret;
 use sqlx::{Connection, PgConnection, PgPool};
+use std::sync::Mutex;
 use uuid::Uuid;
+use wiremock::MockServer;
 
-static TRACING: Lazy<()> = Lazy::new(|| {
+static TRACING: Lazy<LogFilterHandle> = Lazy::new(|| {
     let default_filter_level = "info".to_string();
     let subscriber_name = "test".to_string();
-    if std::env::var("TEST_LOG").is_ok() {
-        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::stdout);
-        init_subscriber(subscriber);
-    } else {
-        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::sink);
-        init_subscriber(subscriber);
-    }
+    // 集成测试不导出 trace，只验证 trace 上下文的传递
+    let tracer_provider = TracerProvider::default();
+    let (subscriber, log_filter) = get_subscriber(
+        subscriber_name,
+        default_filter_level,
+        LogFormat::Bunyan,
+        false,
+        || TestLogs,
+        &tracer_provider,
+    );
+    init_subscriber(subscriber);
+    log_filter
 });
+
+/// 所有测试的日志都保存在内存中，供检查日志内容的测试使用
+static CAPTURED_LOGS: Lazy<Mutex<Vec<u8>>> = Lazy::new(Mutex::default);
+
+/// 日志写入 `CAPTURED_LOGS`；设置了 `TEST_LOG` 时同时输出到标准输出
+struct TestLogs;
+
+impl std::io::Write for TestLogs {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if std::env::var("TEST_LOG").is_ok() {
+            std::io::stdout().write_all(buf)?;
+        }
+        CAPTURED_LOGS.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        std::io::stdout().flush()
+    }
+}
+
+/// 到目前为止所有测试输出的日志
+pub fn captured_logs() -> String {
+    String::from_utf8_lossy(&CAPTURED_LOGS.lock().unwrap()).into_owned()
+}
+
+/// 邮件正文中包含的确认链接
+pub struct ConfirmationLinks {
+    pub html: reqwest::Url,
+    pub plain_text: reqwest::Url,
+}
+
+/// 测试用的管理员账号
+pub struct TestUser {
+    pub user_id: Uuid,
+    pub username: String,
+    pub password: String,
+}
+
+impl TestUser {
+    pub fn generate() -> Self {
+        Self {
+            user_id: Uuid::new_v4(),
+            username: Uuid::new_v4().to_string(),
+            password: Uuid::new_v4().to_string(),
+        }
+    }
+
+    pub async fn login(&self, app: &TestApp) {
+        app.post_login(&serde_json::json!({
+            "username": &self.username,
+            "password": &self.password
+        }))
+        .await;
+    }
+
+    async fn store(&self, pool: &PgPool) {
+        let salt = SaltString::generate(&mut rand::thread_rng());
+        let password_hash = Argon2::new(
+            Algorithm::Argon2id,
+            Version::V0x13,
+            Params::new(15000, 2, 1, None).unwrap(),
+        )
+        .hash_password(self.password.as_bytes(), &salt)
+        .unwrap()
+        .to_string();
+        sqlx::query!(
+            "INSERT INTO users (user_id, username, password_hash) VALUES ($1, $2, $3)",
+            self.user_id,
+            self.username,
+            password_hash,
+        )
+        .execute(pool)
+        .await
+        .expect("Failed to store test user.");
+    }
+}
+
 pub struct TestApp {
     pub address: String,
+    pub port: u16,
+    pub metrics_address: String,
     pub db_pool: PgPool,
+    pub email_server: MockServer,
+    pub test_user: TestUser,
+    pub api_client: reqwest::Client,
+    pub email_client: EmailClient,
+    pub retry_policy: RetryPolicy,
+    pub unsubscribe_links: UnsubscribeLinks,
+    pub tracking_links: TrackingLinks,
+    /// 对应 `tracking.enabled`，只影响 `dispatch_all_pending_emails` 发出的邮件
+    pub tracking_enabled: bool,
+    pub webhook_username: String,
+    pub webhook_password: String,
+}
+
+impl TestApp {
+    /// 同步执行队列中的所有投递任务，代替后台 worker
+    pub async fn dispatch_all_pending_emails(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue = try_execute_task(
+                &self.db_pool,
+                &self.email_client,
+                &self.retry_policy,
+                &self.unsubscribe_links,
+                self.tracking_enabled.then_some(&self.tracking_links),
+            )
+            .await
+            .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/subscriptions", &self.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_subscriptions_with_idempotency_key(
+        &self,
+        body: String,
+        idempotency_key: &str,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/subscriptions", &self.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("Idempotency-Key", idempotency_key)
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_newsletters_with_idempotency_key(
+        &self,
+        body: serde_json::Value,
+        idempotency_key: &str,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/newsletters", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .header("Idempotency-Key", idempotency_key)
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_newsletters(&self, body: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/newsletters", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_postmark_webhook(&self, body: &serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/webhooks/postmark", &self.address))
+            .basic_auth(&self.webhook_username, Some(&self.webhook_password))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_login<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/login", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_login_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/login", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_admin_dashboard(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/dashboard", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_dashboard_html(&self) -> String {
+        self.get_admin_dashboard().await.text().await.unwrap()
+    }
+
+    pub async fn get_change_password(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/password", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_change_password_html(&self) -> String {
+        self.get_change_password().await.text().await.unwrap()
+    }
+
+    pub async fn post_change_password<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/password", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_dead_letters(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/dead_letters", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_metrics(&self) -> String {
+        reqwest::get(format!("{}/metrics", &self.metrics_address))
+            .await
+            .expect("Failed to execute request.")
+            .error_for_status()
+            .unwrap()
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_engagement(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/engagement", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_log_level(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/log_level", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_log_level(&self, filter: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/log_level", &self.address))
+            .form(&[("filter", filter)])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_requeue_dead_letters<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/dead_letters/requeue", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_logout(&self) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/logout", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// 从发送给邮件服务的请求中提取确认链接
+    pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
+        let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+
+        let get_link = |s: &str| {
+            let links: Vec<_> = linkify::LinkFinder::new()
+                .links(s)
+                .filter(|l| *l.kind() == linkify::LinkKind::Url)
+                .collect();
+            assert_eq!(links.len(), 1);
+            let raw_link = links[0].as_str().to_owned();
+            let mut confirmation_link = reqwest::Url::parse(&raw_link).unwrap();
+            // 确保不会请求到外部地址
+            assert_eq!(confirmation_link.host_str().unwrap(), "127.0.0.1");
+            confirmation_link.set_port(Some(self.port)).unwrap();
+            confirmation_link
+        };
+
+        let html = get_link(body["HtmlBody"].as_str().unwrap());
+        let plain_text = get_link(body["TextBody"].as_str().unwrap());
+        ConfirmationLinks { html, plain_text }
+    }
+
+    /// 从邮件的 `List-Unsubscribe` 头中取出退订链接
+    pub fn get_unsubscribe_link(&self, email_request: &wiremock::Request) -> reqwest::Url {
+        let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+        let header = body["Headers"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|h| h["Name"] == "List-Unsubscribe")
+            .unwrap();
+        let raw_link = header["Value"]
+            .as_str()
+            .unwrap()
+            .trim_start_matches('<')
+            .trim_end_matches('>');
+        let mut unsubscribe_link = reqwest::Url::parse(raw_link).unwrap();
+        assert_eq!(unsubscribe_link.host_str().unwrap(), "127.0.0.1");
+        unsubscribe_link.set_port(Some(self.port)).unwrap();
+        unsubscribe_link
+    }
 }
 
 pub async fn spawn_app() -> TestApp {
-    Lazy::force(&TRACING);
+    let log_filter = Lazy::force(&TRACING).clone();
+
+    let email_server = MockServer::start().await;
 
     let configuration = {
         let mut c = get_configuration().expect("Failed to read configuration.");
         c.database.database_name = Uuid::new_v4().to_string();
         c.application.port = 0;
+        c.metrics.port = 0;
+        c.email_client.transport = EmailTransportKind::Postmark;
+        c.email_client.base_url = email_server.uri();
+        c.application.session_store = SessionStoreKind::Memory;
         c
     };
 
     configure_database(&configuration.database).await;
-    let application = Application::build(&configuration)
+    let application = Application::build(&configuration, log_filter)
         .await
         .expect("Failed to build app");
-    let address = format!("http://127.0.0.1:{}", application.port());
-    let _ = tokio::spawn(application.run_until_stopped());
+    let application_port = application.port();
+    let address = format!("http://127.0.0.1:{}", application_port);
+    let metrics_address = format!("http://127.0.0.1:{}", application.metrics_port());
+    tokio::spawn(application.run_until_stopped());
+
+    let api_client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .build()
+        .unwrap();
 
-    TestApp {
+    let test_app = TestApp {
         address,
+        port: application_port,
+        metrics_address,
         db_pool: get_connection_pool(&configuration.database),
-    }
+        email_server,
+        test_user: TestUser::generate(),
+        api_client,
+        email_client: configuration.email_client.client().unwrap(),
+        retry_policy: configuration.delivery_worker.retry_policy(),
+        unsubscribe_links: configuration.application.unsubscribe_links(),
+        tracking_links: configuration.application.tracking_links(),
+        tracking_enabled: configuration.tracking.enabled,
+        webhook_username: configuration.webhooks.username.clone(),
+        webhook_password: configuration.webhooks.password.expose_secret().clone(),
+    };
+    test_app.test_user.store(&test_app.db_pool).await;
+    test_app
 }
 
 async fn configure_database(config: &DatabaseSettings) -> PgPool {
     // 创建数据库
-    let mut connection =
-        PgConnection::connect(&config.connection_string_without_db().expose_secret())
-            .await
-            .expect("Failed to connect to the postgres database");
+    let mut connection = PgConnection::connect_with(&config.without_db())
+        .await
+        .expect("Failed to connect to the postgres database");
     sqlx::query(format!(r#"CREATE DATABASE "{}";"#, config.database_name).as_str())
         .execute(&mut connection)
         .await
         .expect("Failed to create database.");
 
     // 迁移数据
-    let connection_pool = PgPool::connect(&config.connection_string().expose_secret())
+    let connection_pool = PgPool::connect_with(config.with_db())
         .await
         .expect("Failed to connect to the postgres database");
     sqlx::migrate!("./migrations")
@@ -68,3 +447,8 @@ async fn configure_database(config: &DatabaseSettings) -> PgPool {
 
     connection_pool
 }
+
+pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
+    assert_eq!(response.status().as_u16(), 303);
+    assert_eq!(response.headers().get("Location").unwrap(), location);
+}
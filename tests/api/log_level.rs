@@ -0,0 +1,67 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app};
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_the_log_level() {
+    // 准备
+    let app = spawn_app().await;
+
+    // 执行
+    let response = app.get_log_level().await;
+
+    // 断言
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_change_the_log_level() {
+    // 准备
+    let app = spawn_app().await;
+
+    // 执行
+    let response = app.post_log_level("debug").await;
+
+    // 断言
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn the_log_filter_can_be_changed_at_runtime() {
+    // 准备
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let original: serde_json::Value = app.get_log_level().await.json().await.unwrap();
+    let original = original["filter"].as_str().unwrap().to_owned();
+
+    // 执行
+    let response = app.post_log_level("info,sqlx=debug").await;
+    let current: serde_json::Value = app.get_log_level().await.json().await.unwrap();
+    // 集成测试共用同一个全局 subscriber，恢复原来的规则以免影响其他测试
+    app.post_log_level(&original)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // 断言
+    assert_eq!(response.status().as_u16(), 200);
+    let filter = current["filter"].as_str().unwrap();
+    assert!(filter.contains("sqlx=debug"), "{}", filter);
+}
+
+#[tokio::test]
+async fn invalid_log_filters_are_rejected() {
+    // 准备
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // 执行
+    let response = app.post_log_level("sqlx=loud").await;
+
+    // 断言
+    assert_eq!(response.status().as_u16(), 400);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "application/problem+json"
+    );
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["invalid-params"][0]["name"], "filter");
+}
@@ -0,0 +1,177 @@
+use crate::helpers::{captured_logs, spawn_app};
+use actix_demo::telemetry::redact;
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+#[tokio::test]
+async fn subscriber_details_and_tokens_never_appear_in_the_logs() {
+    // 准备
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    // 每次运行使用不同的值，其他测试的日志不会干扰断言
+    let email = format!("{}@example.com", Uuid::new_v4());
+    let name = format!("redaction-{}", Uuid::new_v4());
+
+    // 执行
+    app.post_subscriptions(format!("name={}&email={}", name, email))
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_link = app.get_confirmation_links(email_request).html;
+    let subscription_token = confirmation_link
+        .query_pairs()
+        .find(|(name, _)| name == "subscription_token")
+        .unwrap()
+        .1
+        .into_owned();
+    reqwest::get(confirmation_link)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+    app.post_postmark_webhook(&serde_json::json!({
+        "RecordType": "Bounce",
+        "ID": 4323372036854775807i64,
+        "Type": "HardBounce",
+        "MessageID": "883953f4-6105-42a2-a16a-77a8eac79483",
+        "Details": "Test bounce details",
+        "Email": email,
+        "BouncedAt": "2019-11-05T16:33:54.9070259Z",
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+
+    // 断言
+    let logs = captured_logs();
+    for pii in [&email, &name, &subscription_token, &app.test_user.username] {
+        assert!(!logs.contains(pii.as_str()), "{} appears in the logs", pii);
+    }
+    // 脱敏后的值仍然可以用来关联同一个订阅者的日志
+    let hashed_email = redact(&email).to_string();
+    assert!(
+        logs.lines()
+            .filter(|line| line.contains(&hashed_email))
+            .count()
+            >= 3
+    );
+}
+
+#[tokio::test]
+async fn rejected_tokens_never_appear_in_the_logs() {
+    // 准备
+    let app = spawn_app().await;
+    // 格式正确但签名伪造的退订令牌，以及无法解析的追踪令牌
+    let forged_unsubscribe_token = format!("{}.c2lnbmF0dXJl", Uuid::new_v4().simple());
+    let invalid_tracking_token = format!("not-a-tracking-token-{}", Uuid::new_v4().simple());
+
+    // 执行
+    let unsubscribe = reqwest::Client::new()
+        .post(format!(
+            "{}/subscriptions/unsubscribe?token={}",
+            app.address, forged_unsubscribe_token
+        ))
+        .send()
+        .await
+        .unwrap();
+    let track = reqwest::get(format!("{}/t/{}", app.address, invalid_tracking_token))
+        .await
+        .unwrap();
+
+    // 断言
+    assert_eq!(unsubscribe.status().as_u16(), 401);
+    assert_eq!(track.status().as_u16(), 404);
+    let logs = captured_logs();
+    for token in [&forged_unsubscribe_token, &invalid_tracking_token] {
+        assert!(
+            !logs.contains(token.as_str()),
+            "{} appears in the logs",
+            token
+        );
+        // 拒绝的原因仍然会记录，令牌以脱敏后的形式出现
+        let redacted = format!("{} is not a valid", redact(token));
+        assert!(
+            logs.contains(&redacted),
+            "{} is missing from the logs",
+            redacted
+        );
+    }
+}
+
+#[tokio::test]
+async fn recipient_addresses_in_provider_errors_are_redacted() {
+    // 准备
+    let app = spawn_app().await;
+    let email = format!("{}@example.com", Uuid::new_v4());
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions(format!("name=wangjian&email={}", email))
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    reqwest::get(app.get_confirmation_links(email_request).html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    // Postmark 在错误信息中直接写出了停用的收件人地址
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(serde_json::json!([{
+                "ErrorCode": 406,
+                "Message": format!(
+                    "You tried to send to recipient(s) that have been marked as inactive. \
+                     Found inactive addresses: {}.",
+                    email
+                ),
+            }])),
+        )
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // 执行
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // 断言
+    let last_error: String =
+        sqlx::query_scalar("SELECT last_error FROM issue_delivery_dead_letters")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert!(last_error.contains("marked as inactive"));
+    assert!(!last_error.contains(&email));
+    assert!(!captured_logs().contains(&email));
+}
@@ -0,0 +1,114 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app};
+use actix_demo::authentication::provision_admin;
+use actix_demo::configuration::{get_configuration, AdminSettings};
+use secrecy::{ExposeSecret, SecretBox};
+
+#[tokio::test]
+async fn login_form_is_served() {
+    // 准备
+    let app = spawn_app().await;
+
+    // 执行
+    let response = reqwest::get(format!("{}/login", &app.address))
+        .await
+        .unwrap();
+
+    // 断言
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains(r#"<form action="/login" method="post">"#));
+}
+
+#[tokio::test]
+async fn an_error_flash_message_is_set_on_failure() {
+    // 准备
+    let app = spawn_app().await;
+    let login_body = serde_json::json!({
+        "username": "random-username",
+        "password": "random-password"
+    });
+
+    // 执行
+    let response = app.post_login(&login_body).await;
+
+    // 断言
+    assert_is_redirect_to(&response, "/login");
+
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>Authentication failed.</i></p>"));
+
+    // 刷新页面后错误信息不再显示
+    let html_page = app.get_login_html().await;
+    assert!(!html_page.contains("<p><i>Authentication failed.</i></p>"));
+}
+
+#[tokio::test]
+async fn redirect_to_admin_dashboard_after_login_success() {
+    // 准备
+    let app = spawn_app().await;
+    let login_body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    });
+
+    // 执行
+    let response = app.post_login(&login_body).await;
+
+    // 断言
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+}
+
+/// 本地配置中的管理员账号
+fn configured_admin() -> AdminSettings {
+    get_configuration()
+        .expect("Failed to read configuration.")
+        .admin
+        .expect("The local configuration provisions an admin.")
+}
+
+#[tokio::test]
+async fn the_configured_admin_can_log_in() {
+    // 准备
+    let app = spawn_app().await;
+    let admin = configured_admin();
+
+    // 执行
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": admin.username,
+            "password": admin.password.expose_secret(),
+        }))
+        .await;
+
+    // 断言
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn provisioning_does_not_overwrite_an_existing_admin() {
+    // 准备
+    let app = spawn_app().await;
+    let admin = configured_admin();
+    let other_password = AdminSettings {
+        username: admin.username.clone(),
+        password: SecretBox::new(Box::new("another-long-password".into())),
+    };
+
+    // 执行
+    let created = provision_admin(&other_password, &app.db_pool)
+        .await
+        .unwrap();
+
+    // 断言
+    assert!(!created);
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": admin.username,
+            "password": admin.password.expose_secret(),
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
@@ -1,3 +1,17 @@
+mod admin_dashboard;
+mod change_password;
 mod health_check;
 mod helpers;
+mod log_level;
+mod log_redaction;
+mod login;
+mod metrics;
+mod newsletters;
+mod request_id;
+mod session_store;
 mod subscriptions;
+mod subscriptions_confirm;
+mod subscriptions_unsubscribe;
+mod trace_propagation;
+mod tracking;
+mod webhooks;
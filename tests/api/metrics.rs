@@ -0,0 +1,102 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+#[tokio::test]
+async fn metrics_are_not_served_on_the_public_port() {
+    // 准备
+    let app = spawn_app().await;
+
+    // 执行
+    let response = reqwest::get(format!("{}/metrics", &app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    // 断言
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn requests_are_counted_by_route_template_and_status() {
+    // 准备
+    let app = spawn_app().await;
+
+    // 执行
+    reqwest::get(format!("{}/health_check", &app.address))
+        .await
+        .unwrap();
+    reqwest::get(format!("{}/t/not-a-token", &app.address))
+        .await
+        .unwrap();
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+    let metrics = app.get_metrics().await;
+
+    // 断言
+    assert!(
+        metrics.contains(r#"http_requests_total{method="GET",route="/health_check",status="200"}"#),
+        "{}",
+        metrics
+    );
+    assert!(
+        metrics.contains(r#"http_requests_total{method="GET",route="/t/{token}",status="404"}"#)
+    );
+    assert!(metrics
+        .contains(r#"http_requests_total{method="GET",route="/admin/dashboard",status="303"}"#));
+    assert!(metrics.contains(
+        r#"http_request_duration_seconds_bucket{method="GET",route="/health_check",status="200""#
+    ));
+}
+
+#[tokio::test]
+async fn unmatched_paths_share_a_single_label() {
+    // 准备
+    let app = spawn_app().await;
+
+    // 执行
+    reqwest::get(format!("{}/{}", &app.address, uuid::Uuid::new_v4()))
+        .await
+        .unwrap();
+    let metrics = app.get_metrics().await;
+
+    // 断言
+    assert!(metrics.contains(r#"http_requests_total{method="GET",route="unmatched",status="404"}"#));
+}
+
+#[tokio::test]
+async fn database_pool_metrics_are_exported() {
+    // 准备
+    let app = spawn_app().await;
+
+    // 执行
+    let metrics = app.get_metrics().await;
+
+    // 断言
+    assert!(metrics.contains("db_pool_connections "));
+    assert!(metrics.contains("db_pool_idle_connections "));
+    assert!(metrics.contains("db_pool_max_connections 10"));
+    assert!(metrics.contains("db_pool_acquire_duration_seconds_count "));
+}
+
+#[tokio::test]
+async fn email_sends_are_counted_by_outcome() {
+    // 准备
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // 执行
+    app.post_subscriptions("name=wangjian&email=928647866%40qq.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    let metrics = app.get_metrics().await;
+
+    // 断言
+    assert!(metrics.contains(r#"email_sends_total{operation="single",outcome="success"}"#));
+    assert!(metrics
+        .contains(r#"email_send_duration_seconds_count{operation="single",outcome="success"}"#));
+}
@@ -0,0 +1,617 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, ConfirmationLinks, TestApp};
+use fake::faker::internet::en::SafeEmail;
+use fake::faker::name::en::Name;
+use fake::Fake;
+use std::time::Duration;
+use uuid::Uuid;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+/// 创建一个未确认的订阅者，返回其确认链接
+async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
+    let name: String = Name().fake();
+    let email: String = SafeEmail().fake();
+    let body = serde_urlencoded::to_string(serde_json::json!({
+        "name": name,
+        "email": email
+    }))
+    .unwrap();
+
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Create unconfirmed subscriber")
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_subscriptions(body)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    app.get_confirmation_links(email_request)
+}
+
+/// 创建一个已确认的订阅者
+async fn create_confirmed_subscriber(app: &TestApp) {
+    let confirmation_links = create_unconfirmed_subscriber(app).await;
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
+#[tokio::test]
+async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
+    // 准备
+    let app = spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // 执行
+    let response = app.post_newsletters(newsletter_request_body()).await;
+
+    // 断言
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn newsletters_are_delivered_to_confirmed_subscribers() {
+    // 准备
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // 执行
+    let response = app.post_newsletters(newsletter_request_body()).await;
+
+    // 断言
+    assert_eq!(response.status().as_u16(), 202);
+    let accepted: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(accepted["enqueued"], 1);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn publishing_enqueues_deliveries_instead_of_sending_inline() {
+    // 准备
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .named("No email is sent while handling the request")
+        .mount(&app.email_server)
+        .await;
+
+    // 执行
+    let response = app.post_newsletters(newsletter_request_body()).await;
+
+    // 断言
+    assert_eq!(response.status().as_u16(), 202);
+    let queued = sqlx::query!(r#"SELECT count(*) as "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.count, 2);
+}
+
+#[tokio::test]
+async fn the_worker_empties_the_queue_once_deliveries_are_sent() {
+    // 准备
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletters(newsletter_request_body()).await;
+
+    // 执行
+    app.dispatch_all_pending_emails().await;
+
+    // 断言：两个订阅者的邮件在同一个批量请求中发送
+    let requests = app.email_server.received_requests().await.unwrap();
+    let request = requests
+        .iter()
+        .find(|r| r.url.path() == "/email/batch")
+        .unwrap();
+    let batch: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+    assert_eq!(batch.len(), 2);
+    let queued = sqlx::query!(r#"SELECT count(*) as "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.count, 0);
+}
+
+#[tokio::test]
+async fn newsletters_returns_400_for_invalid_data() {
+    // 准备
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (
+            serde_json::json!({
+                "content": {
+                    "text": "Newsletter body as plain text",
+                    "html": "<p>Newsletter body as HTML</p>",
+                }
+            }),
+            "missing title",
+        ),
+        (
+            serde_json::json!({"title": "Newsletter!"}),
+            "missing content",
+        ),
+    ];
+
+    for (invalid_body, error_message) in test_cases {
+        // 执行
+        let response = app.post_newsletters(invalid_body).await;
+
+        // 断言
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the payload was {}.",
+            error_message
+        );
+    }
+}
+
+#[tokio::test]
+async fn requests_missing_authorization_are_rejected() {
+    // 准备
+    let app = spawn_app().await;
+
+    // 执行
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // 断言
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="publish""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[tokio::test]
+async fn non_existing_user_is_rejected() {
+    // 准备
+    let app = spawn_app().await;
+    let username = Uuid::new_v4().to_string();
+    let password = Uuid::new_v4().to_string();
+
+    // 执行
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .basic_auth(username, Some(password))
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // 断言
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="publish""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[tokio::test]
+async fn invalid_password_is_rejected() {
+    // 准备
+    let app = spawn_app().await;
+    let username = &app.test_user.username;
+    let password = Uuid::new_v4().to_string();
+    assert_ne!(app.test_user.password, password);
+
+    // 执行
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .basic_auth(username, Some(password))
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // 断言
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="publish""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[tokio::test]
+async fn newsletter_creation_is_idempotent() {
+    // 准备
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // 执行：第一次提交
+    let response = app
+        .post_newsletters_with_idempotency_key(newsletter_request_body(), &idempotency_key)
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    let first_body = response.text().await.unwrap();
+
+    // 执行：使用相同的幂等键重试
+    let response = app
+        .post_newsletters_with_idempotency_key(newsletter_request_body(), &idempotency_key)
+        .await;
+
+    // 断言：返回与第一次相同的响应，且邮件只发送了一次
+    assert_eq!(response.status().as_u16(), 202);
+    assert_eq!(response.headers()["Content-Type"], "application/json");
+    assert_eq!(response.text().await.unwrap(), first_body);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn concurrent_newsletter_submissions_are_handled_gracefully() {
+    // 准备
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    // 在事务中锁住订阅表，放慢第一个请求，确保第二个请求在其完成前到达
+    let mut lock = app.db_pool.begin().await.unwrap();
+    sqlx::query("LOCK TABLE subscriptions IN ACCESS EXCLUSIVE MODE")
+        .execute(&mut *lock)
+        .await
+        .unwrap();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        lock.rollback().await.unwrap();
+    });
+
+    // 执行
+    let response1 =
+        app.post_newsletters_with_idempotency_key(newsletter_request_body(), &idempotency_key);
+    let response2 =
+        app.post_newsletters_with_idempotency_key(newsletter_request_body(), &idempotency_key);
+    let (response1, response2) = tokio::join!(response1, response2);
+
+    // 断言
+    assert_eq!(response1.status(), response2.status());
+    assert_eq!(
+        response1.text().await.unwrap(),
+        response2.text().await.unwrap()
+    );
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn an_invalid_idempotency_key_is_rejected_with_a_400() {
+    // 准备
+    let app = spawn_app().await;
+
+    // 执行
+    let response = app
+        .post_newsletters_with_idempotency_key(newsletter_request_body(), &"a".repeat(50))
+        .await;
+
+    // 断言
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+fn queued_deliveries_count_query() -> &'static str {
+    "SELECT count(*) FROM issue_delivery_queue"
+}
+
+#[tokio::test]
+async fn transient_delivery_failures_are_scheduled_for_a_retry() {
+    // 准备
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(503))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletters(newsletter_request_body()).await;
+
+    // 执行
+    app.dispatch_all_pending_emails().await;
+
+    // 断言
+    let task = sqlx::query!(
+        r#"SELECT n_retries, execute_after > now() as "in_the_future!", last_error FROM issue_delivery_queue"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(task.n_retries, 1);
+    assert!(task.in_the_future);
+    assert!(task.last_error.is_some());
+}
+
+#[tokio::test]
+async fn permanent_delivery_failures_are_dead_lettered() {
+    // 准备
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(422))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletters(newsletter_request_body()).await;
+
+    // 执行
+    app.dispatch_all_pending_emails().await;
+
+    // 断言
+    let queued: i64 = sqlx::query_scalar(queued_deliveries_count_query())
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued, 0);
+    let dead_letter = sqlx::query!("SELECT n_retries FROM issue_delivery_dead_letters")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(dead_letter.n_retries, 0);
+}
+
+#[tokio::test]
+async fn deliveries_are_dead_lettered_once_retries_are_exhausted() {
+    // 准备
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let max_retries = app.retry_policy.max_retries;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(u64::from(max_retries) + 1)
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletters(newsletter_request_body()).await;
+
+    // 执行：每次投递后把任务提前到当前时间，模拟退避时间已过
+    for _ in 0..=max_retries {
+        app.dispatch_all_pending_emails().await;
+        sqlx::query!("UPDATE issue_delivery_queue SET execute_after = now()")
+            .execute(&app.db_pool)
+            .await
+            .unwrap();
+    }
+
+    // 断言
+    let queued: i64 = sqlx::query_scalar(queued_deliveries_count_query())
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued, 0);
+    let dead_letter = sqlx::query!("SELECT n_retries FROM issue_delivery_dead_letters")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(dead_letter.n_retries, max_retries as i16);
+}
+
+#[tokio::test]
+async fn each_delivery_in_a_batch_is_handled_according_to_its_own_outcome() {
+    // 准备
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+
+    // 批量请求本身成功，但第一封邮件的收件人已被停用
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            { "ErrorCode": 406, "Message": "Inactive recipient" },
+            { "ErrorCode": 0, "Message": "OK", "MessageID": "b7bc2f4a" },
+        ])))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletters(newsletter_request_body()).await;
+
+    // 执行
+    app.dispatch_all_pending_emails().await;
+
+    // 断言：只有失败的那封进入死信表，成功的那封从队列中删除
+    let queued: i64 = sqlx::query_scalar(queued_deliveries_count_query())
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued, 0);
+    let requests = app.email_server.received_requests().await.unwrap();
+    let request = requests
+        .iter()
+        .find(|r| r.url.path() == "/email/batch")
+        .unwrap();
+    let batch: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+    let dead_letters = sqlx::query!("SELECT subscriber_email FROM issue_delivery_dead_letters")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(dead_letters.len(), 1);
+    assert_eq!(dead_letters[0].subscriber_email, batch[0]["To"]);
+}
+
+#[tokio::test]
+async fn a_failure_to_record_one_outcome_does_not_resend_the_whole_batch() {
+    // 准备
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            { "ErrorCode": 0, "Message": "OK" },
+            { "ErrorCode": 0, "Message": "OK" },
+        ])))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletters(newsletter_request_body()).await;
+
+    // 删除其中一个订阅者的任务时数据库报错
+    let failing_email: String = sqlx::query_scalar("SELECT min(email) FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    sqlx::query(&format!(
+        r#"
+        CREATE FUNCTION fail_delete() RETURNS trigger AS $$
+        BEGIN
+            IF OLD.subscriber_email = '{}' THEN
+                RAISE EXCEPTION 'simulated failure';
+            END IF;
+            RETURN OLD;
+        END;
+        $$ LANGUAGE plpgsql
+        "#,
+        failing_email
+    ))
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query(
+        "CREATE TRIGGER fail_delete BEFORE DELETE ON issue_delivery_queue \
+         FOR EACH ROW EXECUTE FUNCTION fail_delete()",
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // 执行
+    app.dispatch_all_pending_emails().await;
+
+    // 断言：另一个任务的结果已经保存，失败的任务在租约到期前不会随整批再次发送
+    let queued = sqlx::query!(
+        r#"SELECT subscriber_email, execute_after > now() as "leased!" FROM issue_delivery_queue"#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(queued.len(), 1);
+    assert_eq!(queued[0].subscriber_email, failing_email);
+    assert!(queued[0].leased);
+}
+
+#[tokio::test]
+async fn dead_letters_can_be_inspected_and_requeued() {
+    // 准备
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    let _mock_guard = Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(400))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_newsletters(newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+    app.test_user.login(&app).await;
+
+    // 执行
+    let dead_letters: serde_json::Value = app.get_dead_letters().await.json().await.unwrap();
+
+    // 断言
+    let dead_letters = dead_letters.as_array().unwrap();
+    assert_eq!(dead_letters.len(), 1);
+    let newsletter_issue_id = dead_letters[0]["newsletter_issue_id"].as_str().unwrap();
+
+    // 执行
+    let report: serde_json::Value = app
+        .post_requeue_dead_letters(&serde_json::json!({
+            "newsletter_issue_id": newsletter_issue_id
+        }))
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    // 断言
+    assert_eq!(report["requeued"], 1);
+    let task = sqlx::query!("SELECT n_retries FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(task.n_retries, 0);
+    let remaining: i64 = sqlx::query_scalar("SELECT count(*) FROM issue_delivery_dead_letters")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(remaining, 0);
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_dead_letters() {
+    // 准备
+    let app = spawn_app().await;
+
+    // 执行
+    let response = app.get_dead_letters().await;
+
+    // 断言
+    assert_is_redirect_to(&response, "/login");
+}
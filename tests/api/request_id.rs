@@ -0,0 +1,109 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app};
+
+#[tokio::test]
+async fn a_request_id_is_generated_when_none_is_sent() {
+    // 准备
+    let app = spawn_app().await;
+
+    // 执行
+    let first = reqwest::get(format!("{}/health_check", &app.address))
+        .await
+        .unwrap();
+    let second = reqwest::get(format!("{}/health_check", &app.address))
+        .await
+        .unwrap();
+
+    // 断言
+    let first = first.headers()["X-Request-Id"].to_str().unwrap();
+    let second = second.headers()["X-Request-Id"].to_str().unwrap();
+    assert!(uuid::Uuid::parse_str(first).is_ok(), "{}", first);
+    assert_ne!(first, second);
+}
+
+#[tokio::test]
+async fn a_valid_upstream_request_id_is_echoed() {
+    // 准备
+    let app = spawn_app().await;
+
+    // 执行
+    let response = reqwest::Client::new()
+        .get(format!("{}/health_check", &app.address))
+        .header("X-Request-Id", "gateway-7f3a9c")
+        .send()
+        .await
+        .unwrap();
+
+    // 断言
+    assert_eq!(response.headers()["X-Request-Id"], "gateway-7f3a9c");
+}
+
+#[tokio::test]
+async fn an_invalid_upstream_request_id_is_replaced() {
+    // 准备
+    let app = spawn_app().await;
+
+    for invalid in ["has spaces", "<script>", &"a".repeat(200)] {
+        // 执行
+        let response = reqwest::Client::new()
+            .get(format!("{}/health_check", &app.address))
+            .header("X-Request-Id", invalid)
+            .send()
+            .await
+            .unwrap();
+
+        // 断言
+        let request_id = response.headers()["X-Request-Id"].to_str().unwrap();
+        assert!(uuid::Uuid::parse_str(request_id).is_ok(), "{}", request_id);
+    }
+}
+
+#[tokio::test]
+async fn problem_details_include_the_request_id() {
+    // 准备
+    let app = spawn_app().await;
+
+    // 执行
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", &app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("X-Request-Id", "support-ticket-42")
+        .body("name=&email=928647866%40qq.com")
+        .send()
+        .await
+        .unwrap();
+
+    // 断言
+    assert_eq!(response.status().as_u16(), 400);
+    assert_eq!(response.headers()["X-Request-Id"], "support-ticket-42");
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["request_id"], "support-ticket-42");
+    assert_eq!(problem["invalid-params"][0]["name"], "name");
+}
+
+#[tokio::test]
+async fn responses_produced_by_middleware_carry_the_request_id() {
+    // 准备
+    let app = spawn_app().await;
+
+    // 执行
+    let response = app.get_admin_dashboard().await;
+
+    // 断言
+    assert_is_redirect_to(&response, "/login");
+    assert!(response.headers().contains_key("X-Request-Id"));
+}
+
+#[tokio::test]
+async fn unmatched_routes_carry_the_request_id() {
+    // 准备
+    let app = spawn_app().await;
+
+    // 执行
+    let response = reqwest::get(format!("{}/does-not-exist", &app.address))
+        .await
+        .unwrap();
+
+    // 断言
+    assert_eq!(response.status().as_u16(), 404);
+    assert!(response.headers().contains_key("X-Request-Id"));
+}
@@ -0,0 +1,65 @@
+use crate::helpers::spawn_app;
+use actix_demo::session_store::PostgresSessionStore;
+use actix_session::storage::SessionStore;
+use actix_web::cookie::time::Duration;
+use std::collections::HashMap;
+
+fn state() -> HashMap<String, String> {
+    HashMap::from([("user_id".to_string(), "\"42\"".to_string())])
+}
+
+#[tokio::test]
+async fn postgres_session_store_round_trips_session_state() {
+    // 准备
+    let app = spawn_app().await;
+    let store = PostgresSessionStore::new(app.db_pool.clone());
+
+    // 执行
+    let session_key = store.save(state(), &Duration::minutes(5)).await.unwrap();
+
+    // 断言
+    assert_eq!(store.load(&session_key).await.unwrap(), Some(state()));
+
+    let mut updated = state();
+    updated.insert("theme".into(), "\"dark\"".into());
+    let session_key = store
+        .update(session_key, updated.clone(), &Duration::minutes(5))
+        .await
+        .unwrap();
+    assert_eq!(store.load(&session_key).await.unwrap(), Some(updated));
+
+    store.delete(&session_key).await.unwrap();
+    assert_eq!(store.load(&session_key).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn postgres_session_store_ignores_expired_sessions() {
+    // 准备
+    let app = spawn_app().await;
+    let store = PostgresSessionStore::new(app.db_pool.clone());
+
+    // 执行
+    let session_key = store.save(state(), &Duration::seconds(-1)).await.unwrap();
+
+    // 断言
+    assert_eq!(store.load(&session_key).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn postgres_session_store_purges_expired_sessions() {
+    // 准备
+    let app = spawn_app().await;
+    let store = PostgresSessionStore::new(app.db_pool.clone());
+    let expired = store.save(state(), &Duration::seconds(-1)).await.unwrap();
+
+    // 执行
+    let active = store.save(state(), &Duration::minutes(5)).await.unwrap();
+
+    // 断言
+    let keys: Vec<String> = sqlx::query_scalar!("SELECT session_key FROM sessions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(!keys.contains(&expired.as_ref().to_owned()));
+    assert!(keys.contains(&active.as_ref().to_owned()));
+}
@@ -1,38 +1,56 @@
 use crate::helpers::spawn_app;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
 
 #[tokio::test]
 async fn subscribe_returns_a_200_for_valid_form_data() {
     // 准备
     let app = spawn_app().await;
-    let client = reqwest::Client::new();
+    let body = "name=wangjian&email=928647866@qq.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
 
     // 执行
-    let body = "name=wangjian&email=928647866@qq.com";
-    let response = client
-        .post(&format!("{}/subscriptions", &app.address))
-        .header("Content-Type", "application/x-www-form-urlencoded")
-        .body(body)
-        .send()
-        .await
-        .expect("Failed to execute request.");
+    let response = app.post_subscriptions(body.into()).await;
 
     // 断言
     assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn subscribe_persists_the_new_subscriber() {
+    // 准备
+    let app = spawn_app().await;
+    let body = "name=wangjian&email=928647866@qq.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // 执行
+    app.post_subscriptions(body.into()).await;
 
-    let saved = sqlx::query!("SELECT email, name FROM subscriptions")
+    // 断言
+    let saved = sqlx::query!("SELECT email, name, status FROM subscriptions")
         .fetch_one(&app.db_pool)
         .await
         .expect("Failed to fetch saved subscription.");
 
     assert_eq!(saved.email, "928647866@qq.com");
     assert_eq!(saved.name, "wangjian");
+    assert_eq!(saved.status, "pending_confirmation");
 }
 
 #[tokio::test]
 async fn subscribe_returns_a_400_when_fields_are_present_but_invalid() {
     // 准备
     let app = spawn_app().await;
-    let client = reqwest::Client::new();
 
     let test_cases = vec![
         ("name=&email=928647861116@qq.com", "empty name"),
@@ -42,13 +60,7 @@ async fn subscribe_returns_a_400_when_fields_are_present_but_invalid() {
 
     for (body, description) in test_cases {
         // 执行
-        let response = client
-            .post(&format!("{}/subscriptions", &app.address))
-            .header("Content-Type", "application/x-www-form-urlencoded")
-            .body(body)
-            .send()
-            .await
-            .expect("Failed to execute request.");
+        let response = app.post_subscriptions(body.into()).await;
 
         // 断言
         assert_eq!(
@@ -64,7 +76,6 @@ async fn subscribe_returns_a_400_when_fields_are_present_but_invalid() {
 async fn subscribe_returns_a_400_when_data_is_missing() {
     // 准备
     let app = spawn_app().await;
-    let client = reqwest::Client::new();
 
     let test_cases = vec![
         ("name=wangjian", "missing the email"),
@@ -74,13 +85,7 @@ async fn subscribe_returns_a_400_when_data_is_missing() {
 
     for (invalid_body, error_message) in test_cases {
         // 执行
-        let response = client
-            .post(&format!("{}/subscriptions", &app.address))
-            .header("Content-Type", "application/x-www-form-urlencoded")
-            .body(invalid_body)
-            .send()
-            .await
-            .expect("Failed to execute request.");
+        let response = app.post_subscriptions(invalid_body.into()).await;
 
         // 断言
         assert_eq!(
@@ -91,3 +96,285 @@ async fn subscribe_returns_a_400_when_data_is_missing() {
         );
     }
 }
+
+#[tokio::test]
+async fn subscribe_sends_a_confirmation_email_for_valid_data() {
+    // 准备
+    let app = spawn_app().await;
+    let body = "name=wangjian&email=928647866@qq.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // 执行
+    app.post_subscriptions(body.into()).await;
+
+    // 断言：MockServer 在 drop 时校验 expect
+}
+
+#[tokio::test]
+async fn subscribe_sends_a_confirmation_email_with_a_link() {
+    // 准备
+    let app = spawn_app().await;
+    let body = "name=wangjian&email=928647866@qq.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // 执行
+    app.post_subscriptions(body.into()).await;
+
+    // 断言
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    assert_eq!(confirmation_links.html, confirmation_links.plain_text);
+}
+
+#[tokio::test]
+async fn subscribe_fails_if_there_is_a_fatal_database_error() {
+    // 准备
+    let app = spawn_app().await;
+    let body = "name=wangjian&email=928647866@qq.com";
+    // 破坏表结构，模拟数据库故障
+    sqlx::query!("ALTER TABLE subscription_tokens DROP COLUMN subscription_token;")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // 执行
+    let response = app.post_subscriptions(body.into()).await;
+
+    // 断言：不向客户端泄露内部错误细节
+    assert_eq!(response.status().as_u16(), 500);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "application/problem+json"
+    );
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["status"], 500);
+    assert!(problem.get("detail").is_none());
+}
+
+#[tokio::test]
+async fn subscribe_is_idempotent_when_retried_with_the_same_key() {
+    // 准备
+    let app = spawn_app().await;
+    let body = "name=wangjian&email=928647866@qq.com";
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // 执行
+    let first = app
+        .post_subscriptions_with_idempotency_key(body.into(), &idempotency_key)
+        .await;
+    let retry = app
+        .post_subscriptions_with_idempotency_key(body.into(), &idempotency_key)
+        .await;
+
+    // 断言：重试不会因为邮箱唯一约束而失败，也不会重复发送确认邮件
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(retry.status().as_u16(), 200);
+
+    let saved = sqlx::query!("SELECT count(*) as \"count!\" FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to count subscriptions.");
+    assert_eq!(saved.count, 1);
+}
+
+#[tokio::test]
+async fn a_reused_idempotency_key_does_not_replay_another_subscribers_response() {
+    // 准备
+    let app = spawn_app().await;
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // 执行
+    let first = app
+        .post_subscriptions_with_idempotency_key(
+            "name=wangjian&email=928647866%40qq.com".into(),
+            &idempotency_key,
+        )
+        .await;
+    let second = app
+        .post_subscriptions_with_idempotency_key(
+            "name=lisi&email=lisi%40qq.com".into(),
+            &idempotency_key,
+        )
+        .await;
+
+    // 断言：第二个订阅者的请求被真正处理，而不是回放第一个订阅者的响应
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(second.status().as_u16(), 200);
+
+    let saved = sqlx::query!("SELECT count(*) as \"count!\" FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to count subscriptions.");
+    assert_eq!(saved.count, 2);
+}
+
+#[tokio::test]
+async fn subscribe_reports_which_field_is_invalid_as_problem_json() {
+    // 准备
+    let app = spawn_app().await;
+
+    let test_cases = vec![
+        ("name=&email=928647866@qq.com", "name"),
+        ("name=wang%3Cjian%3E&email=928647866@qq.com", "name"),
+        ("name=wangjian&email=", "email"),
+        ("name=wangjian&email=not-an-email", "email"),
+    ];
+
+    for (body, invalid_field) in test_cases {
+        // 执行
+        let response = app.post_subscriptions(body.into()).await;
+
+        // 断言
+        assert_eq!(response.status().as_u16(), 400);
+        assert_eq!(
+            response.headers()["Content-Type"],
+            "application/problem+json"
+        );
+        let problem: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(problem["status"], 400);
+        assert_eq!(
+            problem["invalid-params"][0]["name"], invalid_field,
+            "Unexpected problem details for payload {}: {}",
+            body, problem
+        );
+        assert!(problem["invalid-params"][0]["reason"].is_string());
+    }
+}
+
+#[tokio::test]
+async fn subscribing_again_before_confirming_resends_the_confirmation_email() {
+    // 准备
+    let app = spawn_app().await;
+    let body = "name=wangjian&email=928647866%40qq.com";
+    // 两封确认邮件，以及确认后的欢迎邮件
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(3)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions(body.into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // 执行
+    let response = app.post_subscriptions(body.into()).await;
+
+    // 断言
+    assert_eq!(response.status().as_u16(), 200);
+    let requests = app.email_server.received_requests().await.unwrap();
+    let first = app.get_confirmation_links(&requests[0]);
+    let second = app.get_confirmation_links(&requests[1]);
+    assert_ne!(first.html, second.html);
+    // 只有最新一封邮件中的链接可以完成确认
+    let response = reqwest::get(first.html).await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+    reqwest::get(second.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let tokens = sqlx::query!(r#"SELECT count(*) AS "count!" FROM subscription_tokens"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(tokens.count, 1);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn subscribing_again_after_confirming_is_silently_accepted() {
+    // 准备
+    let app = spawn_app().await;
+    let body = "name=wangjian&email=928647866%40qq.com";
+    // 确认邮件与欢迎邮件，再次订阅时不发送任何邮件
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions(body.into())
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    reqwest::get(app.get_confirmation_links(email_request).html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // 执行
+    let response = app.post_subscriptions(body.into()).await;
+
+    // 断言
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn subscribing_again_after_being_suppressed_is_silently_accepted() {
+    // 准备
+    let app = spawn_app().await;
+    let body = "name=wangjian&email=928647866%40qq.com";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions(body.into())
+        .await
+        .error_for_status()
+        .unwrap();
+    sqlx::query!("UPDATE subscriptions SET status = 'suppressed', suppressed_at = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // 执行
+    let response = app.post_subscriptions(body.into()).await;
+
+    // 断言
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "suppressed");
+}
@@ -0,0 +1,132 @@
+use crate::helpers::spawn_app;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+#[tokio::test]
+async fn confirmations_without_token_are_rejected_with_a_400() {
+    // 准备
+    let app = spawn_app().await;
+
+    // 执行
+    let response = reqwest::get(format!("{}/subscriptions/confirm", app.address))
+        .await
+        .unwrap();
+
+    // 断言
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn confirmations_with_an_unknown_token_are_rejected_with_a_401() {
+    // 准备
+    let app = spawn_app().await;
+
+    // 执行
+    let response = reqwest::get(format!(
+        "{}/subscriptions/confirm?subscription_token=unknowntoken",
+        app.address
+    ))
+    .await
+    .unwrap();
+
+    // 断言
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn the_link_returned_by_subscribe_returns_a_200_if_called() {
+    // 准备
+    let app = spawn_app().await;
+    let body = "name=wangjian&email=928647866@qq.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+
+    // 执行
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    // 断言
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
+    // 准备
+    let app = spawn_app().await;
+    let body = "name=wangjian&email=928647866@qq.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+
+    // 执行
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // 断言
+    let saved = sqlx::query!("SELECT email, name, status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+
+    assert_eq!(saved.email, "928647866@qq.com");
+    assert_eq!(saved.name, "wangjian");
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn confirming_a_subscription_sends_a_single_welcome_email() {
+    // 准备
+    let app = spawn_app().await;
+    let body = "name=wangjian&email=928647866@qq.com";
+
+    // 确认邮件与欢迎邮件
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+
+    // 执行：重复点击确认链接
+    for _ in 0..2 {
+        reqwest::get(confirmation_links.html.clone())
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+    }
+
+    // 断言
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["To"], "928647866@qq.com");
+    assert!(body["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .contains("Your subscription is confirmed"));
+    // 欢迎邮件中的退订链接可以使用
+    let unsubscribe_link = app.get_unsubscribe_link(email_request);
+    let response = reqwest::get(unsubscribe_link).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+}
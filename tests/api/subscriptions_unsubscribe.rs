@@ -0,0 +1,276 @@
+use crate::helpers::{spawn_app, TestApp};
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+/// 订阅并确认，返回确认邮件中的退订链接
+async fn create_confirmed_subscriber(app: &TestApp, body: &str) -> reqwest::Url {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_subscriptions(body.into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    reqwest::get(app.get_confirmation_links(email_request).html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    app.get_unsubscribe_link(email_request)
+}
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
+#[tokio::test]
+async fn unsubscribe_without_token_is_rejected_with_a_400() {
+    // 准备
+    let app = spawn_app().await;
+
+    // 执行
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions/unsubscribe", app.address))
+        .send()
+        .await
+        .unwrap();
+
+    // 断言
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn unsubscribe_with_a_forged_token_is_rejected_with_a_401() {
+    // 准备
+    let app = spawn_app().await;
+    let link = create_confirmed_subscriber(&app, "name=wangjian&email=928647866@qq.com").await;
+    let token = link
+        .query_pairs()
+        .find(|(k, _)| k == "token")
+        .unwrap()
+        .1
+        .into_owned();
+    let (subscriber_id, _) = token.split_once('.').unwrap();
+    let forged = format!("{}.c2lnbmF0dXJl", subscriber_id);
+
+    // 执行
+    let form = reqwest::get(format!(
+        "{}/subscriptions/unsubscribe?token={}",
+        app.address, forged
+    ))
+    .await
+    .unwrap();
+    let response = reqwest::Client::new()
+        .post(format!(
+            "{}/subscriptions/unsubscribe?token={}",
+            app.address, forged
+        ))
+        .send()
+        .await
+        .unwrap();
+
+    // 断言
+    assert_eq!(form.status().as_u16(), 401);
+    assert_eq!(response.status().as_u16(), 401);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn opening_the_unsubscribe_link_does_not_unsubscribe() {
+    // 准备
+    let app = spawn_app().await;
+    let link = create_confirmed_subscriber(&app, "name=wangjian&email=928647866@qq.com").await;
+
+    // 执行
+    let response = reqwest::get(link).await.unwrap();
+
+    // 断言
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().contains(r#"method="post""#));
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn one_click_unsubscribe_marks_the_subscriber_as_unsubscribed() {
+    // 准备
+    let app = spawn_app().await;
+    let link = create_confirmed_subscriber(&app, "name=wangjian&email=928647866@qq.com").await;
+
+    // 执行
+    let response = reqwest::Client::new()
+        .post(link)
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("List-Unsubscribe=One-Click")
+        .send()
+        .await
+        .unwrap();
+
+    // 断言
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT email, status, unsubscribed_at FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.email, "928647866@qq.com");
+    assert_eq!(saved.status, "unsubscribed");
+    assert!(saved.unsubscribed_at.is_some());
+}
+
+#[tokio::test]
+async fn newsletters_are_not_delivered_to_unsubscribed_subscribers() {
+    // 准备
+    let app = spawn_app().await;
+    let link = create_confirmed_subscriber(&app, "name=wangjian&email=928647866@qq.com").await;
+    reqwest::Client::new()
+        .post(link)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // 执行
+    let response = app.post_newsletters(newsletter_request_body()).await;
+
+    // 断言
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn issues_enqueued_before_unsubscribing_are_not_delivered() {
+    // 准备
+    let app = spawn_app().await;
+    let link = create_confirmed_subscriber(&app, "name=wangjian&email=928647866@qq.com").await;
+    app.post_newsletters(newsletter_request_body())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // 只发送退订确认邮件，不投递简报
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email/batch"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // 执行
+    reqwest::Client::new()
+        .post(link)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // 断言
+    app.dispatch_all_pending_emails().await;
+    let queued = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.count, 0);
+}
+
+#[tokio::test]
+async fn an_unsubscribed_email_can_subscribe_again() {
+    // 准备
+    let app = spawn_app().await;
+    let body = "name=wangjian&email=928647866@qq.com";
+    let link = create_confirmed_subscriber(&app, body).await;
+    reqwest::Client::new()
+        .post(link)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // 执行
+    create_confirmed_subscriber(&app, body).await;
+
+    // 断言
+    let saved = sqlx::query!("SELECT status, unsubscribed_at FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].status, "confirmed");
+    assert!(saved[0].unsubscribed_at.is_some());
+}
+
+#[tokio::test]
+async fn unsubscribing_sends_a_single_confirmation_email() {
+    // 准备
+    let app = spawn_app().await;
+    let link = create_confirmed_subscriber(&app, "name=wangjian&email=928647866@qq.com").await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // 执行：重复退订
+    for _ in 0..2 {
+        reqwest::Client::new()
+            .post(link.clone())
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+    }
+
+    // 断言
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["To"], "928647866@qq.com");
+    assert!(body["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .contains("You have been unsubscribed"));
+}
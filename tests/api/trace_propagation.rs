@@ -0,0 +1,76 @@
+use crate::helpers::{spawn_app, TestApp};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+
+/// Postmark 收到的 `traceparent` 请求头
+async fn outgoing_traceparent(app: &TestApp) -> String {
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    email_request.headers["traceparent"]
+        .to_str()
+        .unwrap()
+        .to_owned()
+}
+
+#[tokio::test]
+async fn the_incoming_trace_continues_into_the_email_provider() {
+    // 准备
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // 执行
+    reqwest::Client::new()
+        .post(format!("{}/subscriptions", &app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header(
+            "traceparent",
+            format!("00-{}-00f067aa0ba902b7-01", TRACE_ID),
+        )
+        .body("name=wangjian&email=928647866%40qq.com")
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // 断言
+    let traceparent = outgoing_traceparent(&app).await;
+    assert!(
+        traceparent.starts_with(&format!("00-{}-", TRACE_ID)),
+        "{}",
+        traceparent
+    );
+    // 出站请求的父 span 是本服务中的 span，而不是网关的 span
+    assert!(!traceparent.contains("00f067aa0ba902b7"), "{}", traceparent);
+}
+
+#[tokio::test]
+async fn a_new_trace_is_started_without_an_incoming_traceparent() {
+    // 准备
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // 执行
+    app.post_subscriptions("name=wangjian&email=928647866%40qq.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // 断言
+    let traceparent = outgoing_traceparent(&app).await;
+    let trace_id = traceparent.split('-').nth(1).unwrap();
+    assert_eq!(trace_id.len(), 32);
+    assert_ne!(trace_id, "0".repeat(32));
+    assert_ne!(trace_id, TRACE_ID);
+}
@@ -0,0 +1,229 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+const EMAIL: &str = "928647866@qq.com";
+
+/// 创建一个已确认的订阅者，返回其 id
+async fn create_confirmed_subscriber(app: &TestApp) -> Uuid {
+    // 确认邮件与确认后的欢迎邮件
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_subscriptions(format!("name=wangjian&email={}", EMAIL))
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    sqlx::query!("SELECT id FROM subscriptions WHERE email = $1", EMAIL)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id
+}
+
+/// 发布一期带链接的简报并投递，返回收到的 HTML 正文
+async fn publish_and_deliver(app: &TestApp) -> String {
+    let _mock_guard = Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Read more at https://example.com/article",
+            "html": r#"<p>Read <a href="https://example.com/article">the article</a></p>"#,
+        }
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let batch: Vec<serde_json::Value> = serde_json::from_slice(&email_request.body).unwrap();
+    batch[0]["HtmlBody"].as_str().unwrap().to_owned()
+}
+
+/// 找出 HTML 中所有指向 `/t/` 的追踪地址，并改为测试服务器的端口
+fn tracking_urls(app: &TestApp, html: &str) -> Vec<reqwest::Url> {
+    html.split('"')
+        .filter(|s| s.starts_with("http") && s.contains("/t/"))
+        .map(|s| {
+            let mut url = reqwest::Url::parse(s).unwrap();
+            assert_eq!(url.host_str().unwrap(), "127.0.0.1");
+            url.set_port(Some(app.port)).unwrap();
+            url
+        })
+        .collect()
+}
+
+async fn tracking_event_count(app: &TestApp) -> i64 {
+    sqlx::query!(r#"SELECT count(*) AS "count!" FROM tracking_events"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count
+}
+
+fn no_redirects() -> reqwest::Client {
+    reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn newsletters_are_not_tracked_by_default() {
+    // 准备
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    // 执行
+    let html = publish_and_deliver(&app).await;
+
+    // 断言
+    assert!(tracking_urls(&app, &html).is_empty(), "{}", html);
+    assert!(html.contains(r#"href="https://example.com/article""#));
+}
+
+#[tokio::test]
+async fn tracked_newsletters_contain_a_pixel_and_rewritten_links() {
+    // 准备
+    let mut app = spawn_app().await;
+    app.tracking_enabled = true;
+    create_confirmed_subscriber(&app).await;
+
+    // 执行
+    let html = publish_and_deliver(&app).await;
+
+    // 断言
+    assert!(!html.contains(r#"href="https://example.com/article""#));
+    assert_eq!(tracking_urls(&app, &html).len(), 2);
+    assert!(html.contains("/subscriptions/tracking_opt_out?token="));
+}
+
+#[tokio::test]
+async fn opens_and_clicks_are_recorded_and_aggregated() {
+    // 准备
+    let mut app = spawn_app().await;
+    app.tracking_enabled = true;
+    create_confirmed_subscriber(&app).await;
+    let html = publish_and_deliver(&app).await;
+    let urls = tracking_urls(&app, &html);
+    let (click, open) = (&urls[0], &urls[1]);
+
+    // 执行
+    let pixel = no_redirects().get(open.clone()).send().await.unwrap();
+    let redirect = no_redirects().get(click.clone()).send().await.unwrap();
+    no_redirects().get(click.clone()).send().await.unwrap();
+
+    // 断言
+    assert_eq!(pixel.status().as_u16(), 200);
+    assert_eq!(pixel.headers()["Content-Type"], "image/gif");
+    assert_eq!(redirect.status().as_u16(), 302);
+    assert_eq!(
+        redirect.headers()["Location"],
+        "https://example.com/article"
+    );
+
+    app.test_user.login(&app).await;
+    let engagement: serde_json::Value = app.get_engagement().await.json().await.unwrap();
+    let issue = &engagement[0];
+    assert_eq!(issue["title"], "Newsletter title");
+    assert_eq!(issue["opens"], 1);
+    assert_eq!(issue["unique_opens"], 1);
+    assert_eq!(issue["clicks"], 2);
+    assert_eq!(issue["unique_clicks"], 1);
+}
+
+#[tokio::test]
+async fn subscribers_who_opted_out_are_not_tracked() {
+    // 准备
+    let mut app = spawn_app().await;
+    app.tracking_enabled = true;
+    let subscriber_id = create_confirmed_subscriber(&app).await;
+    let tracked_html = publish_and_deliver(&app).await;
+    let tracked_urls = tracking_urls(&app, &tracked_html);
+    let mut opt_out = reqwest::Url::parse(
+        &app.unsubscribe_links
+            .tracking_opt_out_for_subscriber(subscriber_id),
+    )
+    .unwrap();
+    opt_out.set_port(Some(app.port)).unwrap();
+
+    // 执行
+    reqwest::Client::new()
+        .post(opt_out)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let html = publish_and_deliver(&app).await;
+    // 退出前收到的邮件里的链接仍然可以跳转，但不再记录
+    let redirect = no_redirects()
+        .get(tracked_urls[0].clone())
+        .send()
+        .await
+        .unwrap();
+
+    // 断言
+    assert!(tracking_urls(&app, &html).is_empty(), "{}", html);
+    assert!(!html.contains("/subscriptions/tracking_opt_out"));
+    assert_eq!(redirect.status().as_u16(), 302);
+    assert_eq!(tracking_event_count(&app).await, 0);
+}
+
+#[tokio::test]
+async fn invalid_tracking_tokens_are_rejected() {
+    // 准备
+    let app = spawn_app().await;
+
+    // 执行
+    let response = no_redirects()
+        .get(format!("{}/t/not-a-token", app.address))
+        .send()
+        .await
+        .unwrap();
+
+    // 断言
+    assert_eq!(response.status().as_u16(), 404);
+    assert_eq!(tracking_event_count(&app).await, 0);
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_engagement() {
+    // 准备
+    let app = spawn_app().await;
+
+    // 执行
+    let response = app.get_engagement().await;
+
+    // 断言
+    assert_is_redirect_to(&response, "/login");
+}
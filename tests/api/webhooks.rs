@@ -0,0 +1,343 @@
+use crate::helpers::{spawn_app, TestApp};
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+const EMAIL: &str = "928647866@qq.com";
+
+/// 创建一个已确认的订阅者
+async fn create_confirmed_subscriber(app: &TestApp) {
+    // 确认邮件与确认后的欢迎邮件
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_subscriptions(format!("name=wangjian&email={}", EMAIL))
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+fn bounce(bounce_type: &str) -> serde_json::Value {
+    serde_json::json!({
+        "RecordType": "Bounce",
+        "ID": 4323372036854775807i64,
+        "Type": bounce_type,
+        "TypeCode": 1,
+        "Name": "Hard bounce",
+        "MessageID": "883953f4-6105-42a2-a16a-77a8eac79483",
+        "Description": "The server was unable to deliver your message (ex: unknown user, mailbox not found).",
+        "Details": "Test bounce details",
+        "Email": EMAIL,
+        "From": "sender@example.com",
+        "BouncedAt": "2019-11-05T16:33:54.9070259Z",
+        "Inactive": true,
+        "CanActivate": true,
+        "Subject": "Test subject",
+    })
+}
+
+fn spam_complaint() -> serde_json::Value {
+    serde_json::json!({
+        "RecordType": "SpamComplaint",
+        "ID": 42,
+        "Type": "SpamComplaint",
+        "TypeCode": 512,
+        "MessageID": "00000000-0000-0000-0000-000000000000",
+        "Email": EMAIL,
+        "BouncedAt": "2019-11-05T16:33:54.9070259Z",
+    })
+}
+
+fn delivery() -> serde_json::Value {
+    serde_json::json!({
+        "RecordType": "Delivery",
+        "ServerID": 23,
+        "MessageStream": "outbound",
+        "MessageID": "00000000-0000-0000-0000-000000000000",
+        "Recipient": EMAIL,
+        "Tag": "welcome-email",
+        "DeliveredAt": "2019-11-05T16:33:54.9070259Z",
+        "Details": "Test delivery webhook details",
+    })
+}
+
+async fn subscriber_status(app: &TestApp) -> String {
+    sqlx::query!("SELECT status FROM subscriptions WHERE email = $1", EMAIL)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .status
+}
+
+async fn recorded_events(app: &TestApp) -> Vec<String> {
+    sqlx::query!(
+        "SELECT event_type FROM email_events WHERE email = $1 AND subscriber_id IS NOT NULL",
+        EMAIL
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|r| r.event_type)
+    .collect()
+}
+
+#[tokio::test]
+async fn requests_without_valid_credentials_are_rejected() {
+    // 准备
+    let app = spawn_app().await;
+    let client = reqwest::Client::new();
+    let url = format!("{}/webhooks/postmark", &app.address);
+
+    // 执行
+    let missing = client
+        .post(&url)
+        .json(&bounce("HardBounce"))
+        .send()
+        .await
+        .unwrap();
+    let wrong = client
+        .post(&url)
+        .basic_auth(&app.webhook_username, Some("wrong-password"))
+        .json(&bounce("HardBounce"))
+        .send()
+        .await
+        .unwrap();
+    let wrong_username = client
+        .post(&url)
+        .basic_auth("wrong-username", Some(&app.webhook_password))
+        .json(&bounce("HardBounce"))
+        .send()
+        .await
+        .unwrap();
+
+    // 断言
+    for response in [missing, wrong, wrong_username] {
+        assert_eq!(response.status().as_u16(), 401);
+        assert_eq!(
+            r#"Basic realm="webhooks""#,
+            response.headers()["WWW-Authenticate"]
+        );
+    }
+}
+
+#[tokio::test]
+async fn invalid_payloads_are_rejected_with_a_400() {
+    // 准备
+    let app = spawn_app().await;
+
+    // 执行
+    let response = app
+        .post_postmark_webhook(&serde_json::json!({ "RecordType": "Bounce" }))
+        .await;
+
+    // 断言
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn invalid_payloads_are_not_echoed_in_the_response() {
+    // 准备
+    let app = spawn_app().await;
+    // serde 的错误信息会带上类型不符的值，例如 "invalid type: integer `4242424242`"
+    let mut payload = bounce("HardBounce");
+    payload["Type"] = serde_json::json!(4242424242u64);
+
+    // 执行
+    let response = app.post_postmark_webhook(&payload).await;
+
+    // 断言
+    assert_eq!(response.status().as_u16(), 400);
+    let body = response.text().await.unwrap();
+    assert!(!body.contains("4242424242"), "{}", body);
+}
+
+#[tokio::test]
+async fn a_hard_bounce_suppresses_the_subscriber() {
+    // 准备
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    // 执行
+    let response = app.post_postmark_webhook(&bounce("HardBounce")).await;
+
+    // 断言
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app).await, "suppressed");
+    assert_eq!(recorded_events(&app).await, vec!["bounce"]);
+}
+
+#[tokio::test]
+async fn bounced_addresses_are_matched_regardless_of_case() {
+    // 准备
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let mut payload = bounce("HardBounce");
+    payload["Email"] = serde_json::json!(EMAIL.to_uppercase());
+
+    // 执行
+    let response = app.post_postmark_webhook(&payload).await;
+
+    // 断言
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app).await, "suppressed");
+}
+
+#[tokio::test]
+async fn a_soft_bounce_is_recorded_without_suppressing_the_subscriber() {
+    // 准备
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    // 执行
+    let response = app.post_postmark_webhook(&bounce("SoftBounce")).await;
+
+    // 断言
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app).await, "confirmed");
+    assert_eq!(recorded_events(&app).await, vec!["bounce"]);
+}
+
+#[tokio::test]
+async fn a_spam_complaint_suppresses_the_subscriber() {
+    // 准备
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    // 执行
+    let response = app.post_postmark_webhook(&spam_complaint()).await;
+
+    // 断言
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app).await, "suppressed");
+    assert_eq!(recorded_events(&app).await, vec!["spam_complaint"]);
+}
+
+#[tokio::test]
+async fn deliveries_are_recorded() {
+    // 准备
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    // 执行
+    let response = app.post_postmark_webhook(&delivery()).await;
+
+    // 断言
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app).await, "confirmed");
+    assert_eq!(recorded_events(&app).await, vec!["delivery"]);
+}
+
+#[tokio::test]
+async fn redelivered_events_are_recorded_once() {
+    // 准备
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    // 执行
+    for _ in 0..2 {
+        app.post_postmark_webhook(&bounce("HardBounce"))
+            .await
+            .error_for_status()
+            .unwrap();
+    }
+
+    // 断言
+    assert_eq!(recorded_events(&app).await, vec!["bounce"]);
+}
+
+#[tokio::test]
+async fn unsupported_events_are_acknowledged() {
+    // 准备
+    let app = spawn_app().await;
+
+    // 执行
+    let response = app
+        .post_postmark_webhook(&serde_json::json!({
+            "RecordType": "Open",
+            "MessageID": "00000000-0000-0000-0000-000000000000",
+        }))
+        .await;
+
+    // 断言
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn newsletters_are_not_delivered_to_suppressed_subscribers() {
+    // 准备
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.post_postmark_webhook(&bounce("HardBounce"))
+        .await
+        .error_for_status()
+        .unwrap();
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // 执行
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .await;
+
+    // 断言
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn unsubscribing_does_not_lift_a_suppression() {
+    // 准备
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.post_postmark_webhook(&bounce("HardBounce"))
+        .await
+        .error_for_status()
+        .unwrap();
+    let subscriber_id = sqlx::query!("SELECT id FROM subscriptions WHERE email = $1", EMAIL)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+
+    let mut link =
+        reqwest::Url::parse(&app.unsubscribe_links.for_subscriber(subscriber_id)).unwrap();
+    link.set_port(Some(app.port)).unwrap();
+
+    // 执行
+    reqwest::Client::new()
+        .post(link)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // 断言
+    assert_eq!(subscriber_status(&app).await, "suppressed");
+}